@@ -1,6 +1,7 @@
 //! Voice frame decoding and audio output.
 
 use std::io::Write;
+use std::sync::Arc;
 use std::sync::mpsc::Receiver;
 
 use imbe::consts::SAMPLES_PER_FRAME;
@@ -9,12 +10,16 @@ use imbe::frame::ReceivedFrame;
 use slice_mip::MapInPlace;
 use p25::voice::frame::VoiceFrame;
 
+use pipeline::Pipeline;
+
 /// Messages for `AudioTask`.
 pub enum AudioEvent {
     /// A voice frame was received.
     VoiceFrame(VoiceFrame),
     /// The current voice transmission has been terminated.
     EndTransmission,
+    /// No more voice frames will be received.
+    Finished,
 }
 
 /// Decodes voice frames and outputs them to a stream.
@@ -23,26 +28,40 @@ pub struct AudioTask<W: Write> {
     audio: AudioOutput<W>,
     /// Channel for messages.
     events: Receiver<AudioEvent>,
+    /// Pipeline health counters.
+    pipeline: Arc<Pipeline>,
 }
 
 impl<W: Write> AudioTask<W> {
-    /// Create a new `AudioTask` with the given audio output and event channel.
-    pub fn new(audio: AudioOutput<W>, events: Receiver<AudioEvent>) -> Self {
+    /// Create a new `AudioTask` with the given audio output and event channel, tracking
+    /// its queue depth in the given pipeline counters.
+    pub fn new(audio: AudioOutput<W>, events: Receiver<AudioEvent>,
+               pipeline: Arc<Pipeline>)
+        -> Self
+    {
         AudioTask {
             audio: audio,
             events: events,
+            pipeline: pipeline,
         }
     }
 
-    /// Begin handling events, blocking the current thread.
+    /// Begin handling events, blocking the current thread until the receiver finishes.
     pub fn run(&mut self) {
         loop {
-            match self.events.recv().expect("unable to receive audio event") {
+            let event = self.events.recv().expect("unable to receive audio event");
+            self.pipeline.audio.pop();
+
+            match event {
                 AudioEvent::VoiceFrame(vf) => self.audio.play(&vf),
                 AudioEvent::EndTransmission => {
                    self.audio.flush();
                    self.audio.reset();
                 },
+                AudioEvent::Finished => {
+                    self.audio.flush();
+                    return;
+                },
             }
         }
     }
@@ -0,0 +1,413 @@
+//! Wideband capture split into multiple narrowband channels.
+//!
+//! A polyphase filter bank splits the wideband signal into bins spaced by the baseband
+//! sample rate, each produced at twice the baseband rate. Each receiver chain then takes
+//! the bin nearest its channel, shifts the channel to zero with a fine-tuning oscillator,
+//! and decimates down to the baseband rate. Retuning a chain is purely digital, so the
+//! SDR itself never has to move.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver};
+
+use mio_more;
+use num::complex::Complex32;
+use num::traits::Zero;
+use throttle::Throttler;
+
+use consts::{BUF_SAMPLES, BASEBAND_SAMPLE_RATE};
+use demod::{Demodulator, DemodEvent, Modulation, convert_iq, SPECTRUM_INTERVAL};
+use filter::{self, Fir};
+use hub::HubEvent;
+use nco::Nco;
+use pipeline::Pipeline;
+use profile::DemodProfile;
+use quality::Level;
+use recv::RecvEvent;
+use sdr::{Chunk, ControlTaskEvent};
+use spectrum::{self, Spectrum, SPECTRUM_BINS};
+
+/// Number of prototype filter taps for each bin.
+const TAPS_PER_BIN: usize = 16;
+
+/// Rate of each bin's output (samples/sec).
+const BIN_RATE: u32 = BASEBAND_SAMPLE_RATE * 2;
+
+/// Polyphase filter bank that splits a signal into evenly-spaced frequency bins.
+pub struct Channelizer {
+    /// Number of bins, which is also the number of filter branches.
+    bins: usize,
+    /// Number of input samples per output frame.
+    decim: usize,
+    /// Prototype lowpass filter coefficients.
+    coefs: Vec<f32>,
+    /// Previous inputs, stored twice in a row so the latest window is always contiguous.
+    hist: Vec<Complex32>,
+    /// Index of the oldest sample in the window.
+    idx: usize,
+    /// Input samples seen since the last output frame.
+    pending: usize,
+    /// Index of the latest input sample, modulo the number of bins.
+    time: usize,
+    /// Output of each filter branch for the current frame.
+    branches: Vec<Complex32>,
+    /// Powers of the bin frequency rotation, `exp(-2πjn/bins)`.
+    twiddles: Vec<Complex32>,
+}
+
+impl Channelizer {
+    /// Create a new `Channelizer` with the given number of bins, producing an output
+    /// frame every `decim` input samples.
+    pub fn new(bins: usize, decim: usize) -> Self {
+        let taps = bins * TAPS_PER_BIN;
+
+        Channelizer {
+            bins: bins,
+            decim: decim,
+            // Cut off at the bin spacing, so a channel anywhere in the bin is passed.
+            coefs: filter::lowpass(taps, 1.0 / bins as f32),
+            hist: vec![Complex32::zero(); taps * 2],
+            idx: 0,
+            pending: 0,
+            time: bins - 1,
+            branches: vec![Complex32::zero(); bins],
+            twiddles: (0..bins).map(|n| {
+                Complex32::from_polar(1.0, -2.0 * PI * n as f32 / bins as f32)
+            }).collect(),
+        }
+    }
+
+    /// Feed in the given input sample and return whether a new output frame is ready.
+    pub fn feed(&mut self, s: Complex32) -> bool {
+        let taps = self.coefs.len();
+
+        self.hist[self.idx] = s;
+        self.hist[self.idx + taps] = s;
+        self.idx = (self.idx + 1) % taps;
+        self.time = (self.time + 1) % self.bins;
+        self.pending += 1;
+
+        if self.pending < self.decim {
+            return false;
+        }
+
+        self.pending = 0;
+
+        for b in self.branches.iter_mut() {
+            *b = Complex32::zero();
+        }
+
+        // Accumulate each coefficient into the branch it belongs to, pairing the newest
+        // sample with the first coefficient.
+        let window = &self.hist[self.idx..self.idx + taps];
+
+        for (n, (&c, &x)) in self.coefs.iter().zip(window.iter().rev()).enumerate() {
+            self.branches[n % self.bins] = self.branches[n % self.bins] + x * c;
+        }
+
+        true
+    }
+
+    /// Compute the current frame's output for the given bin, which is centered at
+    /// `bin / bins` times the input sample rate.
+    pub fn bin(&self, bin: usize) -> Complex32 {
+        let sum = self.branches.iter().enumerate().fold(Complex32::zero(), |s, (r, &v)| {
+            s + v * self.twiddles[bin * r % self.bins].conj()
+        });
+
+        sum * self.twiddles[bin * self.time % self.bins]
+    }
+}
+
+/// Extracts and demodulates one channel from the channelizer output.
+struct Tap {
+    /// Channelizer bin containing the channel.
+    bin: usize,
+    /// Whether the channel lies within the captured bandwidth.
+    inband: bool,
+    /// Generation of the current tuning.
+    gen: u32,
+    /// Offset (Hz) of the channel from the wideband center frequency.
+    offset: f32,
+    /// Shifts the channel from its offset within the bin to zero.
+    nco: Nco,
+    /// Anti-aliasing filter for decimating to the baseband rate.
+    lowpass: Fir,
+    /// Alternates so every other bin output is kept when decimating.
+    keep: bool,
+    /// Samples at the baseband rate waiting to be demodulated.
+    buf: Vec<Complex32>,
+    /// Demodulates the channel.
+    demod: Demodulator,
+}
+
+impl Tap {
+    /// Create a new `Tap` feeding the given demodulator.
+    fn new(demod: Demodulator) -> Self {
+        Tap {
+            bin: 0,
+            inband: false,
+            gen: 0,
+            offset: 0.0,
+            nco: Nco::new(0.0, BIN_RATE),
+            lowpass: Fir::new(filter::lowpass(63, 0.15)),
+            keep: false,
+            buf: Vec::with_capacity(BUF_SAMPLES),
+            demod: demod,
+        }
+    }
+
+    /// Feed in the given bin output.
+    fn feed(&mut self, s: Complex32) {
+        // Produce silence for channels that can't be received, so timeouts still elapse.
+        let s = if self.inband { self.nco.mix(s) } else { Complex32::zero() };
+
+        self.lowpass.push(s);
+        self.keep = !self.keep;
+
+        if self.keep {
+            self.buf.push(self.lowpass.output());
+        }
+    }
+
+    /// Demodulate all buffered samples, which were captured with the given power
+    /// spectrum, as produced by `Spectrum::power`, of I/Q sampled at the given rate
+    /// (samples/sec) around the given center frequency (Hz).
+    fn flush(&mut self, power: &[f32], rate: u32, center: u32) {
+        if self.buf.is_empty() {
+            return;
+        }
+
+        let level = Level::measure(power, rate, self.offset);
+
+        self.demod.feed(&mut self.buf[..], self.gen, center, level);
+        self.buf.clear();
+    }
+}
+
+/// Splits wideband I/Q into a channel for each receiver chain and demodulates them.
+pub struct ChannelizerTask {
+    /// Filter bank.
+    bank: Channelizer,
+    /// Wideband sample rate (samples/sec).
+    rate: u32,
+    /// Wideband center frequency (Hz).
+    center: u32,
+    /// Channel extractor for each chain.
+    taps: Vec<Tap>,
+    /// Estimates the spectrum of the I/Q signal to measure each channel's level and
+    /// occasionally send to the hub.
+    spectrum: Spectrum,
+    /// Used to reduce the number of spectrum frames sent.
+    notifier: Throttler,
+    /// Channel for the hub.
+    hub: mio_more::channel::Sender<HubEvent>,
+    /// Tuning control for each chain.
+    tuners: Vec<Receiver<ControlTaskEvent>>,
+    /// Channel for receiving I/Q sample chunks.
+    reader: Receiver<Chunk>,
+    /// Pipeline health counters.
+    pipeline: Arc<Pipeline>,
+}
+
+impl ChannelizerTask {
+    /// Create a new `ChannelizerTask` over I/Q sampled at the given rate (samples/sec)
+    /// around the given center frequency (Hz), with a receiver chain demodulating the
+    /// given modulation under the given profile with the given squelch SNR (dB) for each
+    /// of the given tuning and demodulator channels.
+    ///
+    /// The rate must be a multiple of twice the baseband sample rate.
+    pub fn new(rate: u32,
+               center: u32,
+               modulation: Modulation,
+               profile: DemodProfile,
+               squelch: Option<f32>,
+               reader: Receiver<Chunk>,
+               tuners: Vec<Receiver<ControlTaskEvent>>,
+               demods: Vec<Receiver<DemodEvent>>,
+               hub: mio_more::channel::Sender<HubEvent>,
+               chan: Sender<RecvEvent>,
+               pipeline: Arc<Pipeline>)
+        -> Self
+    {
+        assert!(rate % BIN_RATE == 0);
+        assert!(demods.len() == tuners.len());
+
+        let bins = (rate / BASEBAND_SAMPLE_RATE) as usize;
+
+        ChannelizerTask {
+            bank: Channelizer::new(bins, bins / 2),
+            rate: rate,
+            center: center,
+            taps: demods.into_iter().enumerate().map(|(chain, events)| {
+                Tap::new(Demodulator::new(chain, modulation, profile, squelch, events,
+                                          hub.clone(), chan.clone(), pipeline.clone()))
+            }).collect(),
+            spectrum: Spectrum::new(SPECTRUM_BINS),
+            notifier: Throttler::new(SPECTRUM_INTERVAL),
+            hub: hub,
+            tuners: tuners,
+            reader: reader,
+            pipeline: pipeline,
+        }
+    }
+
+    /// Begin channelizing, blocking the current thread until the sample source is
+    /// exhausted.
+    pub fn run(&mut self) {
+        let mut samples = vec![Complex32::zero(); BUF_SAMPLES];
+
+        // Retuning is applied between chunks, so every chunk belongs to the current
+        // tuning of each chain.
+        while let Ok(chunk) = self.reader.recv() {
+            self.pipeline.iq.pop();
+            self.handle_tuning();
+            convert_iq(&chunk.bytes[..], &mut samples);
+
+            for &s in samples.iter() {
+                if !self.bank.feed(s) {
+                    continue;
+                }
+
+                for tap in self.taps.iter_mut() {
+                    tap.feed(self.bank.bin(tap.bin));
+                }
+            }
+
+            let power = self.spectrum.power(&samples[..]);
+
+            for tap in self.taps.iter_mut() {
+                tap.flush(&power[..], self.rate, chunk.freq);
+            }
+
+            self.send_spectrum(power);
+        }
+
+        for tap in self.taps.iter() {
+            tap.demod.finish();
+        }
+    }
+
+    /// Occasionally send the hub the given power spectrum of the whole capture.
+    fn send_spectrum(&mut self, power: Vec<f32>) {
+        let hub = &self.hub;
+        let rate = self.rate;
+        // The control channel chain is the one reported, as with a narrowband SDR.
+        let offset = -self.taps[0].offset as i32;
+
+        self.notifier.throttle(|| {
+            let dbfs = power.iter().map(|&p| spectrum::decibels(p)).collect();
+
+            hub.send(HubEvent::UpdateSpectrum(rate, offset, dbfs))
+                .expect("unable to send spectrum");
+        });
+    }
+
+    /// Apply any pending tuning changes.
+    fn handle_tuning(&mut self) {
+        for chain in 0..self.tuners.len() {
+            while let Ok(e) = self.tuners[chain].try_recv() {
+                match e {
+                    ControlTaskEvent::SetFreq(freq, gen) => {
+                        self.taps[chain].gen = gen;
+                        self.tune(chain, freq);
+                    },
+                    // Gain and correction only apply to the SDR itself.
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    /// Move the given chain to the given frequency (Hz).
+    fn tune(&mut self, chain: usize, freq: u32) {
+        let offset = freq as i64 - self.center as i64;
+        let tap = &mut self.taps[chain];
+
+        tap.offset = offset as f32;
+
+        // Keep a bin's width of guard at each edge of the capture.
+        tap.inband = offset.abs() < (self.rate / 2 - BASEBAND_SAMPLE_RATE) as i64;
+
+        if !tap.inband {
+            warn!("frequency {} Hz is outside wideband capture", freq);
+            return;
+        }
+
+        let spacing = BASEBAND_SAMPLE_RATE as i64;
+        let bins = self.bank.bins as i64;
+
+        // Find the nearest bin, then shift the remaining offset to zero.
+        let nearest = (offset as f64 / spacing as f64).round() as i64;
+
+        tap.bin = ((nearest % bins + bins) % bins) as usize;
+        tap.nco.set_freq((nearest * spacing - offset) as f32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Measure the average output power of the given bin for a tone at the given
+    /// frequency, as a fraction of the input sample rate.
+    fn bin_power(bins: usize, bin: usize, freq: f32) -> f32 {
+        let mut bank = Channelizer::new(bins, bins / 2);
+        let mut power = 0.0;
+        let mut frames = 0;
+
+        for n in 0..bins * 400 {
+            let s = Complex32::from_polar(1.0, 2.0 * PI * freq * n as f32);
+
+            // Skip filter startup.
+            if bank.feed(s) && n > bins * TAPS_PER_BIN {
+                power += bank.bin(bin).norm_sqr();
+                frames += 1;
+            }
+        }
+
+        power / frames as f32
+    }
+
+    #[test]
+    fn test_channelizer() {
+        // Tones at a bin center pass at unity gain.
+        assert!((bin_power(10, 0, 0.0) - 1.0).abs() < 0.01);
+        assert!((bin_power(10, 3, 0.3) - 1.0).abs() < 0.01);
+        assert!((bin_power(10, 9, -0.1) - 1.0).abs() < 0.01);
+
+        // Tones within half a bin also pass.
+        assert!(bin_power(10, 3, 0.34) > 0.9);
+
+        // Tones in other bins are rejected.
+        assert!(bin_power(10, 0, 0.3) < 1e-4);
+        assert!(bin_power(10, 3, 0.0) < 1e-4);
+        assert!(bin_power(10, 9, 0.1) < 1e-4);
+    }
+
+    #[test]
+    fn test_bin_output() {
+        // A tone offset from the bin center comes out at the offset frequency.
+        let bins = 10;
+        let mut bank = Channelizer::new(bins, bins / 2);
+        let mut prev = Complex32::zero();
+
+        for n in 0..bins * 200 {
+            let s = Complex32::from_polar(1.0, 2.0 * PI * 0.32 * n as f32);
+
+            if !bank.feed(s) {
+                continue;
+            }
+
+            let cur = bank.bin(3);
+
+            if n > bins * TAPS_PER_BIN {
+                // Offset of 0.02 at the input rate over 5 samples per frame.
+                let phase = (cur * prev.conj()).arg();
+                assert!((phase - 2.0 * PI * 0.02 * 5.0).abs() < 0.01);
+            }
+
+            prev = cur;
+        }
+    }
+}
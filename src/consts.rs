@@ -7,7 +7,7 @@ pub const BUF_BYTES: usize = 32768;
 /// Number of samples after transforming byte pairs to complex samples.
 pub const BUF_SAMPLES: usize = BUF_BYTES / 2;
 
-/// Sample rate for the SDR.
+/// Default sample rate for the SDR.
 pub const SDR_SAMPLE_RATE: u32 = 240000;
 /// Downconverted baseband sample rate.
 pub const BASEBAND_SAMPLE_RATE: u32 = 48000;
@@ -0,0 +1,147 @@
+//! Differential QPSK demodulation for CQPSK/LSM simulcast systems.
+//!
+//! Simulcast P25 Phase 1 systems carry the same dibits as C4FM, but each symbol rotates
+//! the carrier phase by ±π/4 or ±3π/4 rather than deviating its frequency. The
+//! amplitude of these signals regularly drops toward zero, which confuses an FM
+//! discriminator. Measuring the phase rotation across each symbol period instead gives
+//! the same waveform that C4FM produces after discrimination and symbol averaging, so
+//! the P25 receiver can decode either.
+
+use std::f32::consts::PI;
+
+use num::complex::Complex32;
+use num::traits::Zero;
+
+use consts::BASEBAND_SAMPLE_RATE;
+use filter::{self, Fir};
+use nco::Nco;
+
+/// P25 symbol rate (symbols/sec).
+const SYMBOL_RATE: u32 = 4800;
+
+/// Baseband samples per symbol.
+const SPS: usize = (BASEBAND_SAMPLE_RATE / SYMBOL_RATE) as usize;
+
+/// Rolloff of the transmitted root-raised-cosine pulse.
+const ROLLOFF: f32 = 0.2;
+
+/// Fraction of the measured carrier offset corrected after each symbol.
+const CARRIER_GAIN: f32 = 0.01;
+
+/// Largest carrier offset correction (Hz), beyond which symbols become ambiguous.
+const CARRIER_LIMIT: f32 = 1200.0;
+
+/// Accumulated timing error that causes the symbol center to move by one sample.
+const TIMING_THRESHOLD: f32 = 4.0;
+
+/// Demodulates π/4-shifted differential QPSK with carrier and symbol tracking.
+pub struct Cqpsk {
+    /// Removes the residual carrier offset.
+    nco: Nco,
+    /// Matched filter for the transmitted pulse.
+    matched: Fir,
+    /// Matched filter outputs over the last symbol period.
+    hist: [Complex32; SPS],
+    /// Index of the oldest output in the history.
+    idx: usize,
+    /// Samples until the next symbol center.
+    count: usize,
+    /// Accumulated symbol timing error.
+    timing: f32,
+    /// Scales the phase rotation (radians) per symbol to the output units.
+    scale: f32,
+}
+
+impl Cqpsk {
+    /// Create a new `Cqpsk` scaling its output so the given frequency deviation (Hz) of
+    /// an equivalent C4FM signal maps to unity.
+    pub fn new(deviation: u32) -> Self {
+        Cqpsk {
+            nco: Nco::new(0.0, BASEBAND_SAMPLE_RATE),
+            matched: Fir::new(filter::root_raised_cosine(SPS * 8 + 1, SPS as f32,
+                                                         ROLLOFF)),
+            hist: [Complex32::zero(); SPS],
+            idx: 0,
+            count: SPS,
+            timing: 0.0,
+            scale: SYMBOL_RATE as f32 / (2.0 * PI * deviation as f32),
+        }
+    }
+
+    /// Demodulate the given sample.
+    pub fn feed(&mut self, s: Complex32) -> f32 {
+        let y = self.matched.feed(self.nco.mix(s));
+
+        // Outputs one symbol and half a symbol ago.
+        let prev = self.hist[self.idx];
+        let mid = self.hist[(self.idx + SPS / 2) % SPS];
+
+        self.hist[self.idx] = y;
+        self.idx = (self.idx + 1) % SPS;
+
+        let rot = (y * prev.conj()).arg();
+
+        self.count -= 1;
+
+        if self.count == 0 {
+            self.count = SPS;
+            self.track(rot, y, prev, mid);
+        }
+
+        rot * self.scale
+    }
+
+    /// Retrieve the estimated carrier offset (Hz) from the tuned frequency.
+    pub fn offset(&self) -> f32 {
+        -self.nco.freq()
+    }
+
+    /// Update the carrier and symbol tracking loops at a symbol center with the given
+    /// phase rotation and the given current, previous, and midpoint filter outputs.
+    fn track(&mut self, rot: f32, cur: Complex32, prev: Complex32, mid: Complex32) {
+        // Any difference from the nearest odd multiple of π/4 is due to carrier offset.
+        let ideal = ((rot / (PI / 2.0)).floor() * 2.0 + 1.0) * PI / 4.0;
+        let offset = (rot - ideal) * SYMBOL_RATE as f32 / (2.0 * PI);
+
+        let freq = self.nco.freq() - CARRIER_GAIN * offset;
+        self.nco.set_freq(freq.max(-CARRIER_LIMIT).min(CARRIER_LIMIT));
+
+        // Gardner timing error, which is positive when sampling early.
+        let power = cur.norm_sqr() + prev.norm_sqr();
+
+        if power > 0.0 {
+            self.timing += ((prev - cur) * mid.conj()).re / power;
+        }
+
+        if self.timing > TIMING_THRESHOLD {
+            self.count += 1;
+            self.timing = 0.0;
+        } else if self.timing < -TIMING_THRESHOLD {
+            self.count -= 1;
+            self.timing = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cqpsk() {
+        let mut d = Cqpsk::new(5000);
+        let mut out = 0.0;
+
+        // A constant rotation of a little over π/4 per symbol looks like a stream of
+        // identical symbols with a 50Hz carrier offset.
+        for n in 0..BASEBAND_SAMPLE_RATE * 2 {
+            let t = n as f32 / BASEBAND_SAMPLE_RATE as f32;
+            out = d.feed(Complex32::from_polar(1.0, 2.0 * PI * 650.0 * t));
+        }
+
+        assert!((d.offset() - 50.0).abs() < 1.0);
+
+        // Same output as C4FM at 600Hz deviation.
+        assert!((out - 0.12).abs() < 1e-3);
+    }
+}
@@ -1,124 +1,557 @@
 //! Demodulation and other signal processing.
 
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::mpsc::{Sender, Receiver};
 use std;
 
 use collect_slice::CollectSlice;
 use demod_fm::FmDemod;
+use fnv::FnvBuildHasher;
 use slice_mip::MapInPlace;
 use mio_more;
 use moving_avg::MovingAverage;
 use num::complex::Complex32;
 use num::traits::Zero;
 use p25_filts::{DecimFir, BandpassFir};
-use pool::{Pool, Checkout};
+use pool::Pool;
 use rtlsdr_iq::IQ;
 use static_decimate::Decimator;
 use static_fir::FIRFilter;
 use throttle::Throttler;
 
+use cqpsk::Cqpsk;
+use filter::{Fir, FirDecimator};
 use hub::HubEvent;
-use recv::RecvEvent;
+use imbalance::IqCorrection;
+use nco::Nco;
+use pipeline::Pipeline;
+use profile::{DemodProfile, PostFilter};
+use quality::Level;
+use recv::{Baseband, RecvEvent};
+use sdr::Chunk;
+use spectrum::{self, Spectrum, SPECTRUM_BINS};
 use consts::{BUF_SAMPLES, BASEBAND_SAMPLE_RATE};
 
+/// Standard FM frequency deviation (Hz), which the demodulator scales to unity.
+pub const DEVIATION: u32 = 5000;
+
+/// Fraction of the measured carrier offset corrected after each chunk.
+const AFC_GAIN: f32 = 0.05;
+
+/// Largest carrier offset correction (Hz), beyond which the carrier falls outside the
+/// channel filter anyway.
+const AFC_LIMIT: f32 = 3000.0;
+
+/// Number of I/Q chunks between spectrum frames.
+pub const SPECTRUM_INTERVAL: usize = 8;
+
+/// Amount (dB) the SNR must fall below the squelch level to close an open squelch.
+const SQUELCH_HYSTERESIS: f32 = 3.0;
+
+/// Largest baseband mean square power for which a carrier is considered present.
+///
+/// Noise demodulates to values spread well beyond the deviation, while a captured C4FM
+/// carrier stays within it.
+const AFC_SQUELCH: f32 = 0.25;
+
+/// Modulation of the received P25 signal.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Modulation {
+    /// Continuous 4-level FM, used by most systems.
+    C4fm,
+    /// Compatible QPSK, or linear simulcast modulation, used by simulcast systems.
+    Cqpsk,
+}
+
+impl Modulation {
+    /// Retrieve the name of the modulation, as accepted by `from_str`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Modulation::C4fm => "c4fm",
+            Modulation::Cqpsk => "cqpsk",
+        }
+    }
+}
+
+impl FromStr for Modulation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c4fm" => Ok(Modulation::C4fm),
+            "cqpsk" => Ok(Modulation::Cqpsk),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Messages for a demodulator.
+pub enum DemodEvent {
+    /// Switch to the given modulation.
+    SetModulation(Modulation),
+    /// Switch to the given profile.
+    SetProfile(DemodProfile),
+}
+
 /// Demodulates raw I/Q signal to C4FM baseband.
 pub struct DemodTask {
-    /// Decimates I/Q signal.
-    decim: Decimator<DecimFir>,
-    /// Channel-select lowpass filter.
-    bandpass: FIRFilter<BandpassFir>,
-    /// Moving average filter.
-    avg: MovingAverage<f32>,
-    /// Demodulates FM signal.
-    demod: FmDemod,
-    /// Channel for receiving I/Q sample chunks.
-    reader: Receiver<Checkout<Vec<u8>>>,
+    /// Receiver chain being demodulated.
+    chain: usize,
+    /// I/Q sample rate (samples/sec).
+    rate: u32,
+    /// Offset (Hz) of the SDR center frequency from the channel.
+    offset: i32,
+    /// Estimates the spectrum of the I/Q signal, to measure the channel level and
+    /// occasionally send to the hub.
+    spectrum: Spectrum,
+    /// Used to reduce the number of spectrum frames sent.
+    notifier: Throttler,
     /// Channel for the hub.
     hub: mio_more::channel::Sender<HubEvent>,
-    /// Channel for sending baseband sample chunks.
-    chan: Sender<RecvEvent>,
+    /// DC offset and I/Q imbalance correction for each center frequency (Hz), which
+    /// vary with the tuning.
+    corrections: HashMap<u32, IqCorrection, FnvBuildHasher>,
+    /// Shifts the channel to zero when the SDR is tuned away from it.
+    nco: Option<Nco>,
+    /// Decimates I/Q signal down to 5 times the baseband rate.
+    stages: Vec<FirDecimator>,
+    /// Decimates I/Q signal from 5 times the baseband rate.
+    decim: Option<Decimator<DecimFir>>,
+    /// Demodulates the decimated signal.
+    demod: Demodulator,
+    /// Channel for receiving I/Q sample chunks.
+    reader: Receiver<Chunk>,
+    /// Pipeline health counters.
+    pipeline: Arc<Pipeline>,
 }
 
 impl DemodTask {
-    /// Create a new `DemodTask` to communicate on the given channels.
-    pub fn new(reader: Receiver<Checkout<Vec<u8>>>,
+    /// Create a new `DemodTask` for the given receiver chain over I/Q sampled at the
+    /// given rate (samples/sec), with the SDR tuned the given offset (Hz) away from the
+    /// channel, demodulating the given modulation under the given profile with the given
+    /// squelch SNR (dB), to communicate on the given channels. The given pipeline
+    /// counters are shared with the SDR reader, whose queue this task drains.
+    ///
+    /// The rate must be a multiple of the baseband sample rate.
+    pub fn new(chain: usize,
+               rate: u32,
+               offset: i32,
+               modulation: Modulation,
+               profile: DemodProfile,
+               squelch: Option<f32>,
+               events: Receiver<DemodEvent>,
+               reader: Receiver<Chunk>,
                hub: mio_more::channel::Sender<HubEvent>,
-               chan: Sender<RecvEvent>)
+               chan: Sender<RecvEvent>,
+               pipeline: Arc<Pipeline>)
         -> Self
     {
+        assert!(rate % BASEBAND_SAMPLE_RATE == 0);
+
+        let factor = (rate / BASEBAND_SAMPLE_RATE) as usize;
+
+        // Use the filter designed for P25 for the last stage when the rate allows it.
+        let (stages, decim) = if factor % 5 == 0 {
+            (decim_stages(factor / 5), Some(Decimator::new(5)))
+        } else {
+            (decim_stages(factor), None)
+        };
+
         DemodTask {
-            decim: Decimator::new(5),
-            bandpass: FIRFilter::new(),
-            avg: MovingAverage::new(10),
-            // Assume a 5kHz frequency deviation.
-            demod: FmDemod::new(5000, BASEBAND_SAMPLE_RATE),
+            chain: chain,
+            rate: rate,
+            offset: offset,
+            spectrum: Spectrum::new(SPECTRUM_BINS),
+            notifier: Throttler::new(SPECTRUM_INTERVAL),
+            hub: hub.clone(),
+            corrections: HashMap::default(),
+            // The channel sits below the center by the offset.
+            nco: if offset == 0 { None } else { Some(Nco::new(offset as f32, rate)) },
+            stages: stages.into_iter().map(FirDecimator::new).collect(),
+            decim: decim,
+            demod: Demodulator::new(chain, modulation, profile, squelch, events, hub,
+                                    chan, pipeline.clone()),
             reader: reader,
-            hub: hub,
-            chan: chan,
+            pipeline: pipeline,
         }
     }
 
-    /// Begin demodulating, blocking the current thread.
+    /// Begin demodulating, blocking the current thread until the sample source is
+    /// exhausted.
     pub fn run(&mut self) {
-        let mut pool = Pool::with_capacity(16, || vec![0.0; BUF_SAMPLES]);
         let mut samples = vec![Complex32::zero(); BUF_SAMPLES];
 
-        // Used to reduce the number of signal level messages sent.
-        let mut notifier = Throttler::new(4);
+        // Finish up once the sample source has been exhausted.
+        while let Ok(chunk) = self.reader.recv() {
+            self.pipeline.iq.pop();
+            convert_iq(&chunk.bytes[..], &mut samples);
 
-        loop {
-            let bytes = self.reader.recv().expect("unable to receive sdr samples");
+            self.corrections.entry(chunk.freq)
+                .or_insert_with(IqCorrection::default)
+                .correct(&mut samples[..]);
 
-            // This is safe because it's transforming an array of N 8-bit words to an
-            // array of N/2 16-bit words.
-            let pairs = unsafe {
-                std::slice::from_raw_parts(bytes.as_ptr() as *const u16, BUF_SAMPLES)
-            };
+            let power = self.spectrum.power(&samples[..]);
+            let level = Level::measure(&power[..], self.rate, -self.offset as f32);
 
-            // This is safe because it equals the original allocation length.
-            unsafe { samples.set_len(BUF_SAMPLES); }
+            // Only the first chain reports its spectrum, like its signal power.
+            if self.chain == 0 {
+                self.send_spectrum(power);
+            }
 
-            // Transform interleaved byte pairs to complex floating point samples.
-            pairs.iter()
-                 .map(|&s| IQ[s])
-                 .collect_slice(&mut samples[..]);
+            if let Some(ref mut nco) = self.nco {
+                samples.map_in_place(|&s| nco.mix(s));
+            }
 
             // Decimate from SDR to baseband sample rate.
-            let len = self.decim.decim_in_place(&mut samples[..]);
+            let mut len = samples.len();
+
+            for stage in self.stages.iter_mut() {
+                len = stage.decim_in_place(&mut samples[..len]);
+            }
+
+            if let Some(ref mut decim) = self.decim {
+                len = decim.decim_in_place(&mut samples[..len]);
+            }
 
             // This is safe because the decimated length is less than the original length.
             unsafe { samples.set_len(len); }
 
-            // Apply bandpass filter to attenuate out-of-channel interference.
-            samples.map_in_place(|&s| self.bandpass.feed(s));
+            self.demod.feed(&mut samples[..], chunk.gen, chunk.freq, level);
+        }
+
+        self.demod.finish();
+    }
+
+    /// Occasionally send the hub the given power spectrum.
+    fn send_spectrum(&mut self, power: Vec<f32>) {
+        let hub = &self.hub;
+        let rate = self.rate;
+        let offset = self.offset;
+
+        self.notifier.throttle(|| {
+            let dbfs = power.iter().map(|&p| spectrum::decibels(p)).collect();
+
+            hub.send(HubEvent::UpdateSpectrum(rate, offset, dbfs))
+                .expect("unable to send spectrum");
+        });
+    }
+}
+
+/// Demodulates I/Q signal at the baseband sample rate to C4FM baseband and sends it to
+/// the receiver.
+pub struct Demodulator {
+    /// Receiver chain the baseband belongs to.
+    chain: usize,
+    /// Current modulation.
+    modulation: Modulation,
+    /// Lowest channel SNR (dB) considered to hold a carrier.
+    squelch: Option<f32>,
+    /// Whether the channel currently holds a carrier.
+    carrier: bool,
+    /// Current profile.
+    profile: DemodProfile,
+    /// Removes the carrier frequency offset.
+    afc: Afc,
+    /// Channel-select filter designed for P25.
+    bandpass: FIRFilter<BandpassFir>,
+    /// Channel-select filter designed for the profile, used instead of the P25 filter.
+    channel: Option<Fir>,
+    /// Moving average filter, if the profile uses it.
+    avg: Option<MovingAverage<f32>>,
+    /// Demodulates FM signal.
+    demod: FmDemod,
+    /// Demodulates CQPSK signal.
+    cqpsk: Cqpsk,
+    /// Channel for receiving demodulator messages.
+    events: Receiver<DemodEvent>,
+    /// Baseband sample chunks.
+    pool: Pool<Vec<f32>>,
+    /// Used to reduce the number of signal level messages sent.
+    notifier: Throttler,
+    /// Channel for the hub.
+    hub: mio_more::channel::Sender<HubEvent>,
+    /// Channel for sending baseband sample chunks.
+    chan: Sender<RecvEvent>,
+    /// Pipeline health counters.
+    pipeline: Arc<Pipeline>,
+}
+
+impl Demodulator {
+    /// Create a new `Demodulator` for the given receiver chain, initially demodulating the
+    /// given modulation under the given profile, with the given squelch SNR (dB), to
+    /// communicate on the given channels.
+    ///
+    /// Baseband chunks are counted in the given pipeline as they're queued for the
+    /// receiver, or as they're dropped when the receiver holds every buffer.
+    pub fn new(chain: usize,
+               modulation: Modulation,
+               profile: DemodProfile,
+               squelch: Option<f32>,
+               events: Receiver<DemodEvent>,
+               hub: mio_more::channel::Sender<HubEvent>,
+               chan: Sender<RecvEvent>,
+               pipeline: Arc<Pipeline>)
+        -> Self
+    {
+        Demodulator {
+            chain: chain,
+            modulation: modulation,
+            squelch: squelch,
+            carrier: true,
+            profile: profile,
+            afc: Afc::new(profile.deviation),
+            bandpass: FIRFilter::new(),
+            channel: profile.channel.coefs().map(Fir::new),
+            avg: post_filter(profile.post),
+            demod: FmDemod::new(profile.deviation, BASEBAND_SAMPLE_RATE),
+            cqpsk: Cqpsk::new(profile.deviation),
+            events: events,
+            pool: Pool::with_capacity(16, || vec![0.0; BUF_SAMPLES]),
+            notifier: Throttler::new(4),
+            hub: hub,
+            chan: chan,
+            pipeline: pipeline,
+        }
+    }
+
+    /// Switch to the given profile, keeping the current carrier offset correction.
+    fn set_profile(&mut self, profile: DemodProfile) {
+        info!("switching to {} demodulator profile", profile.name);
+
+        self.profile = profile;
+        self.afc.deviation = profile.deviation;
+        self.channel = profile.channel.coefs().map(Fir::new);
+        self.avg = post_filter(profile.post);
+        self.demod = FmDemod::new(profile.deviation, BASEBAND_SAMPLE_RATE);
+        self.cqpsk = Cqpsk::new(profile.deviation);
+    }
+
+    /// Switch to the given modulation, starting the carrier offset correction over.
+    fn set_modulation(&mut self, modulation: Modulation) {
+        info!("switching to {} demodulation", modulation.name());
+
+        // CQPSK tracks the carrier itself, so the C4FM correction would otherwise stay
+        // frozen at whatever it last measured.
+        self.modulation = modulation;
+        self.afc = Afc::new(self.profile.deviation);
+        self.cqpsk = Cqpsk::new(self.profile.deviation);
+    }
+
+    /// Demodulate the given chunk of samples, which are filtered in place and were
+    /// captured under the given tuning generation at the given center frequency (Hz)
+    /// with the given channel level, and send the resulting baseband to the receiver.
+    pub fn feed(&mut self, samples: &mut [Complex32], gen: u32, freq: u32, level: Level) {
+        while let Ok(e) = self.events.try_recv() {
+            match e {
+                DemodEvent::SetModulation(m) => self.set_modulation(m),
+                DemodEvent::SetProfile(p) => self.set_profile(p),
+            }
+        }
+
+        // Center the carrier, then apply bandpass filter to attenuate out-of-channel
+        // interference.
+        match self.channel {
+            Some(ref mut channel) => {
+                let afc = &mut self.afc;
+                samples.map_in_place(|&s| channel.feed(afc.nco.mix(s)));
+            },
+            None => samples.map_in_place(|&s| self.bandpass.feed(self.afc.nco.mix(s))),
+        }
+
+        let hub = &self.hub;
+        let chain = self.chain;
+
+        let offset = match self.modulation {
+            Modulation::C4fm => self.afc.offset(),
+            Modulation::Cqpsk => self.cqpsk.offset(),
+        };
 
-            notifier.throttle(|| {
+        self.notifier.throttle(|| {
+            // Only the first chain reports signal power, so multiple chains don't
+            // interleave their levels.
+            if chain == 0 {
                 // Calculate power assuming a "normalized" resistance.
                 let power = power_dbm(&samples[..], 1.0);
 
-                self.hub.send(HubEvent::UpdateSignalPower(power))
+                hub.send(HubEvent::UpdateSignalPower(power))
                     .expect("unable to send signal power");
-            });
+            }
+
+            hub.send(HubEvent::UpdateFreqOffset(chain, freq, offset))
+                .expect("unable to send frequency offset");
+        });
+
+        let mut baseband = match self.pool.checkout() {
+            Some(b) => b,
+            None => {
+                // The receiver has fallen behind, and holding up the SDR would only move
+                // the backlog upstream.
+                self.pipeline.record_baseband_drop();
+                return;
+            },
+        };
+
+        // This is safe because each input sample produces exactly one output sample.
+        unsafe { baseband.set_len(samples.len()); }
+
+        match self.modulation {
+            Modulation::C4fm => {
+                // Demodulate FM signal to C4FM baseband.
+                samples.iter()
+                       .map(|&s| self.demod.feed(s))
+                       .collect_slice(&mut baseband[..]);
+
+                // Apply averaging filter.
+                if let Some(ref mut avg) = self.avg {
+                    baseband.map_in_place(|&s| avg.feed(s));
+                }
+
+                self.afc.update(&baseband[..]);
+            },
+            Modulation::Cqpsk => {
+                // The symbol rotations already match averaged C4FM baseband, and the
+                // carrier is tracked internally.
+                samples.iter()
+                       .map(|&s| self.cqpsk.feed(s))
+                       .collect_slice(&mut baseband[..]);
+            },
+        }
+
+        self.pipeline.baseband.push();
+        self.chan.send(RecvEvent::Baseband(Baseband {
+            chain: self.chain,
+            gen: gen,
+            level: level,
+            deviation: self.profile.deviation,
+            carrier: self.update_squelch(&level),
+            samples: baseband,
+        })).expect("unable to send baseband");
+    }
+
+    /// Update the squelch with the given channel level and return whether a carrier is
+    /// present.
+    fn update_squelch(&mut self, level: &Level) -> bool {
+        let squelch = match self.squelch {
+            Some(s) => s,
+            None => return true,
+        };
+
+        // Require a larger drop to close the squelch so fades don't chop up a call.
+        self.carrier = if self.carrier {
+            level.snr >= squelch - SQUELCH_HYSTERESIS
+        } else {
+            level.snr >= squelch
+        };
+
+        self.carrier
+    }
+
+    /// Notify the receiver that no more samples will be demodulated.
+    pub fn finish(&self) {
+        self.chan.send(RecvEvent::Finished(self.chain)).expect("unable to send finish");
+    }
+}
 
-            let mut baseband = pool.checkout().expect("unable to allocate baseband");
+/// Create the baseband filter for the given choice, if any.
+fn post_filter(post: PostFilter) -> Option<MovingAverage<f32>> {
+    match post {
+        PostFilter::Average(len) => Some(MovingAverage::new(len)),
+        PostFilter::Bypass => None,
+    }
+}
+
+/// Split the given decimation factor into the stages, largest first, that decimate by
+/// it in total.
+fn decim_stages(mut factor: usize) -> Vec<usize> {
+    let mut stages = vec![];
+    let mut n = 2;
+
+    // Prime factors keep each stage's filter short.
+    while factor > 1 {
+        if factor % n == 0 {
+            stages.push(n);
+            factor /= n;
+        } else {
+            n += 1;
+        }
+    }
 
-            // This is safe because each input sample produces exactly one output sample.
-            unsafe { baseband.set_len(samples.len()); }
+    stages.reverse();
+    stages
+}
+
+/// Estimates the carrier frequency offset from the C4FM baseband and removes it.
+///
+/// C4FM symbols are symmetric around the carrier, so any lasting baseband mean is due
+/// to the carrier being offset from the tuned frequency.
+struct Afc {
+    /// Shifts the carrier to zero.
+    nco: Nco,
+    /// FM frequency deviation (Hz) scaled to unity in the baseband.
+    deviation: u32,
+}
+
+impl Afc {
+    /// Create a new `Afc` with no correction for baseband with the given deviation (Hz)
+    /// scaled to unity.
+    fn new(deviation: u32) -> Self {
+        Afc {
+            nco: Nco::new(0.0, BASEBAND_SAMPLE_RATE),
+            deviation: deviation,
+        }
+    }
 
-            // Demodulate FM signal to C4FM baseband.
-            samples.iter()
-                   .map(|&s| self.demod.feed(s))
-                   .collect_slice(&mut baseband[..]);
+    /// Update the correction with the remaining offset measured in the given chunk of
+    /// corrected baseband.
+    fn update(&mut self, baseband: &[f32]) {
+        if baseband.is_empty() {
+            return;
+        }
 
-            // Apply averaging filter.
-            baseband.map_in_place(|&s| self.avg.feed(s));
+        let len = baseband.len() as f32;
+        let mean = baseband.iter().sum::<f32>() / len;
+        let power = baseband.iter().fold(0.0, |s, &x| s + x * x) / len;
 
-            self.chan.send(RecvEvent::Baseband(baseband))
-                .expect("unable to send baseband");
+        // Don't chase noise when there's no carrier.
+        if power > AFC_SQUELCH {
+            return;
         }
+
+        let freq = self.nco.freq() - AFC_GAIN * mean * self.deviation as f32;
+        self.nco.set_freq(freq.max(-AFC_LIMIT).min(AFC_LIMIT));
+    }
+
+    /// Retrieve the estimated carrier offset (Hz) from the tuned frequency.
+    fn offset(&self) -> f32 {
+        -self.nco.freq()
     }
 }
 
+/// Transform the given interleaved 8-bit I/Q byte pairs into complex floating point
+/// samples, replacing the contents of the given buffer.
+///
+/// The buffer must have been allocated with a capacity of at least half the number of
+/// bytes.
+pub fn convert_iq(bytes: &[u8], samples: &mut Vec<Complex32>) {
+    let len = bytes.len() / 2;
+    assert!(samples.capacity() >= len);
+
+    // This is safe because it's transforming an array of N 8-bit words to an array of N/2
+    // 16-bit words.
+    let pairs = unsafe {
+        std::slice::from_raw_parts(bytes.as_ptr() as *const u16, len)
+    };
+
+    // This is safe because it's within the original allocation length.
+    unsafe { samples.set_len(len); }
+
+    pairs.iter()
+         .map(|&s| IQ[s])
+         .collect_slice(&mut samples[..]);
+}
+
 /// Calculate the power (dBm) into the resistance (ohms) of the given samples.
 pub fn power_dbm(samples: &[Complex32], resistance: f32) -> f32 {
     // Units of Watt-ohms
@@ -132,3 +565,242 @@ pub fn power_dbm(samples: &[Complex32], resistance: f32) -> f32 {
     // Convert Watts to dBm.
     30.0 + 10.0 * power.log10()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use p25::message::nid::DataUnit;
+    use p25::message::receiver::{MessageReceiver, MessageEvent};
+    use p25::trunking::fields::TalkGroup;
+    use p25::trunking::tsbk::{self, TsbkFields, TsbkOpcode};
+    use p25::voice::control::{self, LinkControlFields, LinkControlOpcode};
+    use consts::{BUF_BYTES, SDR_SAMPLE_RATE};
+    use modulator::{self, Modulator};
+    use profile::DemodProfile;
+
+    /// NAC used for generated signals.
+    const NAC: u16 = 0x293;
+
+    /// Group voice link control word carried by the generated LDU, for talkgroup 0x1234
+    /// from unit 0xABCDEF.
+    const LC: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x12, 0x34, 0xAB, 0xCD, 0xEF];
+
+    /// Build a control channel signal with a group voice grant on either side of an LDU,
+    /// surrounded by idle symbols.
+    fn signal() -> Vec<u8> {
+        let grant = modulator::tsbk(0x00, 0x00,
+            [0x00, 0x11, 0x23, 0x12, 0x34, 0x00, 0xAB, 0xCD]);
+
+        // Alternate the outer symbols so the idle signal has no mean.
+        let idle = |n| (0..n).map(|i| if i % 2 == 0 { 0b01 } else { 0b11 })
+            .collect::<Vec<u8>>();
+
+        let mut dibits = idle(240);
+        dibits.extend(modulator::tsbk_unit(NAC, grant));
+        dibits.extend(idle(48));
+        dibits.extend(modulator::ldu1_unit(NAC, &modulator::ldu1_body(LC)[..]));
+        dibits.extend(idle(48));
+        dibits.extend(modulator::tsbk_unit(NAC, grant));
+        dibits.extend(idle(480));
+        dibits
+    }
+
+    /// Demodulate the given I/Q bytes sampled at the default SDR rate through a
+    /// `DemodTask` with the given modulation and decode the resulting baseband.
+    fn receive(iq: &[u8], modulation: Modulation) -> Vec<MessageEvent> {
+        let (tx_read, rx_read) = channel();
+        let (tx_recv, rx_recv) = channel();
+        let (_tx_events, rx_events) = channel();
+        let (tx_hub, _rx_hub) = mio_more::channel::channel();
+
+        let mut demod = DemodTask::new(0, SDR_SAMPLE_RATE, 0, modulation,
+            DemodProfile::default(), None, rx_events, rx_read, tx_hub, tx_recv,
+            Arc::new(Pipeline::default()));
+
+        let chunks = iq.chunks(BUF_BYTES);
+        let mut pool = Pool::with_capacity(chunks.len(), || vec![0; BUF_BYTES]);
+
+        for piece in chunks {
+            let mut bytes = pool.checkout().expect("unable to allocate samples");
+            bytes.truncate(piece.len());
+            bytes.copy_from_slice(piece);
+
+            tx_read.send(Chunk { gen: 0, freq: 0, bytes: bytes })
+                .expect("unable to send samples");
+        }
+
+        drop(tx_read);
+        demod.run();
+
+        let mut msg = MessageReceiver::new();
+        let mut events = vec![];
+
+        for e in rx_recv.try_iter() {
+            if let RecvEvent::Baseband(bb) = e {
+                events.extend(bb.samples.iter().filter_map(|&s| msg.feed(s)));
+            }
+        }
+
+        events
+    }
+
+    /// Check that the given events are exactly the generated packets, in order.
+    fn check_events(events: &[MessageEvent]) {
+        use p25::message::receiver::MessageEvent::*;
+
+        let kinds: Vec<&str> = events.iter().map(|e| match *e {
+            PacketNID(ref nid) => match nid.data_unit {
+                DataUnit::TrunkingSignaling => "tsbk nid",
+                DataUnit::VoiceLCFrameGroup => "ldu1 nid",
+                _ => "other nid",
+            },
+            TrunkingControl(_) => "tsbk",
+            VoiceFrame(_) => "voice",
+            LinkControl(_) => "lc",
+            LowSpeedDataFragment(_) => "lsd",
+            Error(_) => "error",
+            _ => "other",
+        }).collect();
+
+        let mut expected = vec!["tsbk nid", "tsbk", "ldu1 nid"];
+
+        // The link control word is complete after its last fragment, which follows the
+        // seventh voice frame, and the low speed data follows the eighth.
+        expected.extend(vec!["voice"; 7]);
+        expected.extend(vec!["lc", "voice", "lsd", "voice"]);
+        expected.extend(vec!["tsbk nid", "tsbk"]);
+
+        assert_eq!(kinds, expected);
+
+        for e in events.iter() {
+            match *e {
+                TrunkingControl(tsbk) => check_grant(tsbk),
+                LinkControl(lc) => check_lc(lc),
+                _ => {},
+            }
+        }
+    }
+
+    /// Check that the given packet is the generated group voice grant.
+    fn check_grant(tsbk: TsbkFields) {
+        assert!(tsbk.crc_valid());
+        assert_eq!(tsbk.mfg(), 0);
+
+        match tsbk.opcode() {
+            Some(TsbkOpcode::GroupVoiceGrant) => {},
+            _ => panic!("unexpected opcode"),
+        }
+
+        let grant = tsbk::GroupVoiceGrant::new(tsbk);
+
+        match grant.talkgroup() {
+            TalkGroup::Other(tg) => assert_eq!(tg, 0x1234),
+            _ => panic!("unexpected talkgroup"),
+        }
+
+        assert_eq!(grant.channel().id(), 1);
+        assert_eq!(grant.channel().number(), 0x123);
+    }
+
+    /// Check that the given link control word is the generated group voice word.
+    fn check_lc(lc: LinkControlFields) {
+        match lc.opcode() {
+            Some(LinkControlOpcode::GroupVoiceTraffic) => {},
+            _ => panic!("unexpected opcode"),
+        }
+
+        let lc = control::GroupVoiceTraffic::new(lc);
+
+        match lc.talkgroup() {
+            TalkGroup::Other(tg) => assert_eq!(tg, 0x1234),
+            _ => panic!("unexpected talkgroup"),
+        }
+
+        assert_eq!(lc.src_unit(), 0xABCDEF);
+    }
+
+    #[test]
+    fn test_demod_clean() {
+        let mut m = Modulator::new(SDR_SAMPLE_RATE, 1800.0, 0.0, 0.0);
+        check_events(&receive(&m.modulate(&signal()[..])[..], Modulation::C4fm)[..]);
+    }
+
+    #[test]
+    fn test_demod_impaired() {
+        // Noisy, slightly underdeviated, and off frequency.
+        let mut m = Modulator::new(SDR_SAMPLE_RATE, 1700.0, 300.0, 0.1);
+        check_events(&receive(&m.modulate(&signal()[..])[..], Modulation::C4fm)[..]);
+    }
+
+    #[test]
+    fn test_demod_cqpsk_clean() {
+        let mut m = Modulator::new(SDR_SAMPLE_RATE, 1800.0, 0.0, 0.0);
+        check_events(&receive(&m.modulate_cqpsk(&signal()[..])[..],
+                              Modulation::Cqpsk)[..]);
+    }
+
+    #[test]
+    fn test_demod_cqpsk_impaired() {
+        // Noisy and off frequency.
+        let mut m = Modulator::new(SDR_SAMPLE_RATE, 1800.0, 300.0, 0.1);
+        check_events(&receive(&m.modulate_cqpsk(&signal()[..])[..],
+                              Modulation::Cqpsk)[..]);
+    }
+
+    #[test]
+    fn test_decim_stages() {
+        assert_eq!(decim_stages(1), Vec::<usize>::new());
+        assert_eq!(decim_stages(4), vec![2, 2]);
+        assert_eq!(decim_stages(5), vec![5]);
+        assert_eq!(decim_stages(24), vec![3, 2, 2, 2]);
+        assert_eq!(decim_stages(10), vec![5, 2]);
+    }
+
+    #[test]
+    fn test_afc() {
+        let mut afc = Afc::new(DEVIATION);
+        assert_eq!(afc.offset(), 0.0);
+
+        // A carrier 500Hz high shifts the baseband mean up by 0.1.
+        afc.update(&[0.1, 0.46, -0.26, 0.22, -0.02][..]);
+        assert!((afc.offset() - 25.0).abs() < 1e-2);
+
+        // Noise is ignored.
+        afc.update(&[1.5, -1.2, 0.9, -1.4][..]);
+        assert!((afc.offset() - 25.0).abs() < 1e-2);
+
+        // The correction is limited.
+        for _ in 0..10000 {
+            afc.update(&[0.3; 4][..]);
+        }
+
+        assert!((afc.offset() - AFC_LIMIT).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_set_modulation() {
+        let (tx_recv, _rx_recv) = channel();
+        let (_tx_events, rx_events) = channel();
+        let (tx_hub, _rx_hub) = mio_more::channel::channel();
+
+        let mut demod = Demodulator::new(1, Modulation::C4fm, DemodProfile::default(),
+            None, rx_events, tx_hub, tx_recv, Arc::new(Pipeline::default()));
+
+        demod.afc.nco.set_freq(-100.0);
+        assert!((demod.afc.offset() - 100.0).abs() < 1e-3);
+
+        // The C4FM correction isn't left applied while CQPSK tracks the carrier.
+        demod.set_modulation(Modulation::Cqpsk);
+        assert_eq!(demod.afc.offset(), 0.0);
+        assert_eq!(demod.cqpsk.offset(), 0.0);
+    }
+
+    #[test]
+    fn test_modulation() {
+        assert_eq!("c4fm".parse(), Ok(Modulation::C4fm));
+        assert_eq!("cqpsk".parse(), Ok(Modulation::Cqpsk));
+        assert_eq!("lsm".parse::<Modulation>(), Err(()));
+        assert_eq!(Modulation::Cqpsk.name().parse(), Ok(Modulation::Cqpsk));
+    }
+}
@@ -0,0 +1,256 @@
+//! Runtime-designed FIR filters.
+
+use std::f32::consts::PI;
+
+use num::complex::Complex32;
+use num::traits::Zero;
+
+/// Design a Blackman-windowed sinc lowpass filter with the given number of taps and
+/// cutoff frequency.
+///
+/// The cutoff is given as a fraction of the sample rate, in (0, 0.5), and the resulting
+/// filter has unity gain at DC.
+pub fn lowpass(taps: usize, cutoff: f32) -> Vec<f32> {
+    assert!(taps > 0);
+    assert!(cutoff > 0.0 && cutoff < 0.5);
+
+    let mid = (taps - 1) as f32 / 2.0;
+
+    let mut coefs: Vec<f32> = (0..taps).map(|n| {
+        let t = n as f32 - mid;
+
+        let sinc = if t == 0.0 {
+            2.0 * cutoff
+        } else {
+            (2.0 * PI * cutoff * t).sin() / (PI * t)
+        };
+
+        let window = if taps == 1 {
+            1.0
+        } else {
+            let x = 2.0 * PI * n as f32 / (taps - 1) as f32;
+            0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos()
+        };
+
+        sinc * window
+    }).collect();
+
+    let sum: f32 = coefs.iter().sum();
+
+    for c in coefs.iter_mut() {
+        *c /= sum;
+    }
+
+    coefs
+}
+
+/// Design a root-raised-cosine pulse shaping filter with the given number of taps,
+/// samples per symbol, and rolloff factor.
+///
+/// The resulting filter has unity gain at DC.
+pub fn root_raised_cosine(taps: usize, sps: f32, alpha: f32) -> Vec<f32> {
+    assert!(taps > 0);
+    assert!(alpha > 0.0 && alpha <= 1.0);
+
+    let mid = (taps - 1) as f32 / 2.0;
+
+    let mut coefs: Vec<f32> = (0..taps).map(|n| {
+        // Time in symbols.
+        let t = (n as f32 - mid) / sps;
+
+        if t == 0.0 {
+            1.0 - alpha + 4.0 * alpha / PI
+        } else if ((4.0 * alpha * t).abs() - 1.0).abs() < 1e-6 {
+            // Avoid the singularity at t = ±1/4α.
+            alpha / 2.0f32.sqrt() * ((1.0 + 2.0 / PI) * (PI / (4.0 * alpha)).sin() +
+                                     (1.0 - 2.0 / PI) * (PI / (4.0 * alpha)).cos())
+        } else {
+            ((PI * t * (1.0 - alpha)).sin() +
+                4.0 * alpha * t * (PI * t * (1.0 + alpha)).cos()) /
+            (PI * t * (1.0 - (4.0 * alpha * t).powi(2)))
+        }
+    }).collect();
+
+    let sum: f32 = coefs.iter().sum();
+
+    for c in coefs.iter_mut() {
+        *c /= sum;
+    }
+
+    coefs
+}
+
+/// FIR filter over complex samples with real coefficients.
+pub struct Fir {
+    /// Filter coefficients.
+    coefs: Vec<f32>,
+    /// Previous inputs, stored twice in a row so the latest window is always contiguous.
+    hist: Vec<Complex32>,
+    /// Index of the oldest sample in the window.
+    idx: usize,
+}
+
+impl Fir {
+    /// Create a new `Fir` with the given coefficients.
+    pub fn new(coefs: Vec<f32>) -> Self {
+        Fir {
+            hist: vec![Complex32::zero(); coefs.len() * 2],
+            coefs: coefs,
+            idx: 0,
+        }
+    }
+
+    /// Add the given sample to the filter history without computing an output.
+    pub fn push(&mut self, s: Complex32) {
+        let len = self.coefs.len();
+
+        self.hist[self.idx] = s;
+        self.hist[self.idx + len] = s;
+        self.idx = (self.idx + 1) % len;
+    }
+
+    /// Compute the filter output at the current history.
+    pub fn output(&self) -> Complex32 {
+        let len = self.coefs.len();
+        let window = &self.hist[self.idx..self.idx + len];
+
+        // Coefficients are applied newest-first.
+        self.coefs.iter().zip(window.iter().rev()).fold(Complex32::zero(), |s, (&c, &x)| {
+            s + x * c
+        })
+    }
+
+    /// Feed in the given sample and return the filtered output.
+    pub fn feed(&mut self, s: Complex32) -> Complex32 {
+        self.push(s);
+        self.output()
+    }
+}
+
+/// Lowpass filter that keeps every Nth output.
+pub struct FirDecimator {
+    /// Anti-aliasing filter.
+    fir: Fir,
+    /// Decimation factor.
+    factor: usize,
+    /// Inputs seen since the last kept output.
+    phase: usize,
+}
+
+impl FirDecimator {
+    /// Create a new `FirDecimator` reducing the sample rate by the given factor.
+    pub fn new(factor: usize) -> Self {
+        assert!(factor > 1);
+
+        FirDecimator {
+            // Leave a transition band below the output Nyquist frequency, so only signal
+            // well outside the channel aliases.
+            fir: Fir::new(lowpass(16 * factor + 1, 0.4 / factor as f32)),
+            factor: factor,
+            phase: 0,
+        }
+    }
+
+    /// Decimate the given samples in place and return the number of output samples,
+    /// which are at the front of the slice.
+    pub fn decim_in_place(&mut self, samples: &mut [Complex32]) -> usize {
+        let mut len = 0;
+
+        for i in 0..samples.len() {
+            self.fir.push(samples[i]);
+            self.phase += 1;
+
+            // Only kept outputs need to be computed.
+            if self.phase == self.factor {
+                self.phase = 0;
+                samples[len] = self.fir.output();
+                len += 1;
+            }
+        }
+
+        len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lowpass() {
+        let h = lowpass(31, 0.1);
+        assert_eq!(h.len(), 31);
+
+        // Symmetric with unity DC gain.
+        assert!((h.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        for i in 0..15 {
+            assert!((h[i] - h[30 - i]).abs() < 1e-6);
+        }
+
+        // Strongly attenuates near Nyquist.
+        let gain = h.iter().enumerate().fold(0.0, |s, (n, &c)| {
+            s + if n % 2 == 0 { c } else { -c }
+        });
+
+        assert!(gain.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_root_raised_cosine() {
+        let h = root_raised_cosine(81, 10.0, 0.2);
+        assert_eq!(h.len(), 81);
+        assert!((h.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        // Symmetric and peaked at the center.
+        for i in 0..40 {
+            assert!((h[i] - h[80 - i]).abs() < 1e-6);
+            assert!(h[i] < h[40]);
+        }
+
+        // Cascading two gives a Nyquist pulse, with little energy at other symbol
+        // centers.
+        let rc: Vec<f32> = (0..161).map(|n| {
+            (0..81).filter(|&k| n >= k && n - k < 81).fold(0.0, |s, k| s + h[k] * h[n - k])
+        }).collect();
+
+        for sym in 1..8 {
+            assert!((rc[80 + sym * 10] / rc[80]).abs() < 0.03);
+        }
+    }
+
+    #[test]
+    fn test_fir() {
+        let mut f = Fir::new(vec![1.0, 2.0, 3.0]);
+
+        assert_eq!(f.feed(Complex32::new(1.0, 0.0)), Complex32::new(1.0, 0.0));
+        assert_eq!(f.feed(Complex32::new(0.0, 1.0)), Complex32::new(2.0, 1.0));
+        assert_eq!(f.feed(Complex32::new(0.0, 0.0)), Complex32::new(3.0, 2.0));
+        assert_eq!(f.feed(Complex32::new(0.0, 0.0)), Complex32::new(0.0, 3.0));
+        assert_eq!(f.feed(Complex32::new(0.0, 0.0)), Complex32::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_decimator() {
+        let mut d = FirDecimator::new(4);
+
+        // Phase carries across chunks.
+        let mut samples = vec![Complex32::new(1.0, -1.0); 1000];
+        assert_eq!(d.decim_in_place(&mut samples[..6]), 1);
+        assert_eq!(d.decim_in_place(&mut samples[..10]), 3);
+        assert_eq!(d.decim_in_place(&mut samples[..]), 250);
+
+        // DC passes once the filter has filled.
+        assert!((samples[249] - Complex32::new(1.0, -1.0)).norm() < 1e-4);
+
+        // A tone that would alias near DC is rejected.
+        let mut d = FirDecimator::new(4);
+
+        let mut samples: Vec<Complex32> = (0..1000).map(|n| {
+            Complex32::from_polar(1.0, 2.0 * PI * 0.24 * n as f32)
+        }).collect();
+
+        assert_eq!(d.decim_in_place(&mut samples[..]), 250);
+        assert!(samples[100..250].iter().all(|s| s.norm() < 1e-3));
+    }
+}
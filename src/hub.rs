@@ -27,8 +27,15 @@ use uhttp_status::StatusCode;
 use uhttp_uri::HttpResource;
 use uhttp_version::HttpVersion;
 
+use demod::{DemodEvent, Modulation};
+use profile::{DemodProfile, PROFILES};
 use http;
+use motorola::{self, MotorolaOpcode, ChannelRef};
+use pipeline::{BacklogStats, PipelineStats};
+use quality::SignalQuality;
 use recv::RecvEvent;
+use scope::Snapshot;
+use sdr::{ControlTaskEvent, TunerSettings};
 use talkgroups::GroupCryptoMap;
 
 /// Available routes.
@@ -41,6 +48,18 @@ enum Route {
     Encrypted,
     /// Reset stat counters.
     ResetStats,
+    /// Get/Set tuner gain.
+    Gain,
+    /// Get supported tuner gains.
+    Gains,
+    /// Get/Set tuner frequency correction.
+    Ppm,
+    /// Get/Set demodulator modulation.
+    Demod,
+    /// Get/Set demodulator profile.
+    Profile,
+    /// Get current frequency and talkgroup of each chain.
+    Chains,
 }
 
 impl<'a> TryFrom<HttpResource<'a>> for Route {
@@ -52,6 +71,12 @@ impl<'a> TryFrom<HttpResource<'a>> for Route {
             "/ctlfreq" => Ok(Route::CtlFreq),
             "/encrypted" => Ok(Route::Encrypted),
             "/stats/reset" => Ok(Route::ResetStats),
+            "/gain" => Ok(Route::Gain),
+            "/gains" => Ok(Route::Gains),
+            "/ppm" => Ok(Route::Ppm),
+            "/demod" => Ok(Route::Demod),
+            "/profile" => Ok(Route::Profile),
+            "/chains" => Ok(Route::Chains),
             _ => Err(StatusCode::NotFound),
         }
     }
@@ -125,14 +150,32 @@ pub struct HubTask {
     chan: Receiver<HubEvent>,
     /// Channel for communication with RecvTask.
     recv: Sender<RecvEvent>,
+    /// Channels for controlling each SDR, with the control channel device first.
+    tuners: Vec<Sender<ControlTaskEvent>>,
+    /// Channels for controlling each demodulator.
+    demods: Vec<Sender<DemodEvent>>,
 }
 
 impl HubTask {
     /// Create a new `HubTask` to communicate on the given channels and bind to the given
     /// socket address.
-    pub fn new(chan: Receiver<HubEvent>, recv: Sender<RecvEvent>, addr: &SocketAddr)
+    ///
+    /// The given settings were initially applied to each tuner, the given gains are
+    /// those supported by the control channel device, and the given modulation and
+    /// profile were initially selected in each demodulator.
+    pub fn new(chan: Receiver<HubEvent>,
+               recv: Sender<RecvEvent>,
+               tuners: Vec<Sender<ControlTaskEvent>>,
+               settings: Vec<TunerSettings>,
+               gains: Vec<i32>,
+               demods: Vec<Sender<DemodEvent>>,
+               modulation: Modulation,
+               profile: DemodProfile,
+               addr: &SocketAddr)
         -> std::io::Result<Self>
     {
+        assert!(tuners.len() == settings.len());
+
         let socket = TcpListener::bind(addr)?;
         let events = Poll::new()?;
 
@@ -140,12 +183,20 @@ impl HubTask {
         events.register(&chan, HubToken::Events.into(), Ready::readable(), PollOpt::edge())?;
 
         Ok(HubTask {
-            state: State::default(),
+            state: State {
+                settings: settings,
+                gains: gains,
+                modulation: modulation,
+                profile: profile,
+                ..State::default()
+            },
             socket: socket,
             events: events,
             streamers: ArrayVec::new(),
             chan: chan,
             recv: recv,
+            tuners: tuners,
+            demods: demods,
         })
     }
 
@@ -305,6 +356,137 @@ impl HubTask {
 
                 Ok(())
             },
+            (Method::Get, Route::Gain) => {
+                let gains: Vec<Option<i32>> = self.state.settings.iter()
+                    .map(|s| s.gain).collect();
+
+                http::send_json(req.into_stream(), json!({
+                    "gain": gains,
+                })).ok();
+
+                Ok(())
+            },
+            (Method::Put, Route::Gain) => {
+                let msg: SerdeGain = req.read_json()?;
+
+                if msg.device >= self.tuners.len() {
+                    return Err(StatusCode::BadRequest);
+                }
+
+                let event = match msg.gain {
+                    Some(g) => {
+                        if !self.state.gains.is_empty() && !self.state.gains.contains(&g) {
+                            return Err(StatusCode::BadRequest);
+                        }
+
+                        ControlTaskEvent::SetGain(g)
+                    },
+                    None => ControlTaskEvent::EnableAgc,
+                };
+
+                if self.tuners[msg.device].send(event).is_err() {
+                    return Err(StatusCode::InternalServerError);
+                }
+
+                self.state.settings[msg.device].gain = msg.gain;
+                http::send_status(req.into_stream(), StatusCode::Ok).ok();
+
+                Ok(())
+            },
+            (Method::Get, Route::Gains) => {
+                http::send_json(req.into_stream(), json!({
+                    "gains": &self.state.gains,
+                })).ok();
+
+                Ok(())
+            },
+            (Method::Get, Route::Ppm) => {
+                let ppms: Vec<i32> = self.state.settings.iter().map(|s| s.ppm).collect();
+
+                http::send_json(req.into_stream(), json!({
+                    "ppm": ppms,
+                })).ok();
+
+                Ok(())
+            },
+            (Method::Put, Route::Ppm) => {
+                let msg: SerdePpm = req.read_json()?;
+
+                if msg.device >= self.tuners.len() {
+                    return Err(StatusCode::BadRequest);
+                }
+
+                if self.tuners[msg.device].send(ControlTaskEvent::SetPpm(msg.ppm)).is_err() {
+                    return Err(StatusCode::InternalServerError);
+                }
+
+                self.state.settings[msg.device].ppm = msg.ppm;
+                http::send_status(req.into_stream(), StatusCode::Ok).ok();
+
+                Ok(())
+            },
+            (Method::Get, Route::Demod) => {
+                http::send_json(req.into_stream(), json!({
+                    "demod": self.state.modulation.name(),
+                })).ok();
+
+                Ok(())
+            },
+            (Method::Put, Route::Demod) => {
+                let msg: SerdeDemod = req.read_json()?;
+
+                let modulation: Modulation = match msg.demod.parse() {
+                    Ok(m) => m,
+                    Err(()) => return Err(StatusCode::BadRequest),
+                };
+
+                for demod in self.demods.iter() {
+                    if demod.send(DemodEvent::SetModulation(modulation)).is_err() {
+                        return Err(StatusCode::InternalServerError);
+                    }
+                }
+
+                self.state.modulation = modulation;
+                http::send_status(req.into_stream(), StatusCode::Ok).ok();
+
+                Ok(())
+            },
+            (Method::Get, Route::Profile) => {
+                let names: Vec<&str> = PROFILES.iter().map(|p| p.name).collect();
+
+                http::send_json(req.into_stream(), json!({
+                    "profile": self.state.profile.name,
+                    "profiles": names,
+                })).ok();
+
+                Ok(())
+            },
+            (Method::Put, Route::Profile) => {
+                let msg: SerdeProfile = req.read_json()?;
+
+                let profile: DemodProfile = match msg.profile.parse() {
+                    Ok(p) => p,
+                    Err(()) => return Err(StatusCode::BadRequest),
+                };
+
+                for demod in self.demods.iter() {
+                    if demod.send(DemodEvent::SetProfile(profile)).is_err() {
+                        return Err(StatusCode::InternalServerError);
+                    }
+                }
+
+                self.state.profile = profile;
+                http::send_status(req.into_stream(), StatusCode::Ok).ok();
+
+                Ok(())
+            },
+            (Method::Get, Route::Chains) => {
+                http::send_json(req.into_stream(), json!({
+                    "chains": &self.state.chains,
+                })).ok();
+
+                Ok(())
+            },
             (Method::Options, _) => {
                 let mut h = HeaderLines::new(req.into_stream());
 
@@ -337,9 +519,43 @@ impl HubTask {
             State(UpdateChannelParams(_)) => Ok(()),
             State(UpdateEncrypted(..)) =>
                 SerdeEvent::new("updateEncrypted", &self.state.encrypted).write(s),
-            UpdateCurFreq(f) => SerdeEvent::new("curFreq", f).write(s),
-            UpdateTalkGroup(tg) => SerdeEvent::new("talkGroup", tg).write(s),
+            State(UpdateCurFreq(chain, f)) => SerdeEvent::new("curFreq", json!({
+                "chain": chain,
+                "freq": f,
+            })).write(s),
+            State(UpdateTalkGroup(chain, tg)) => SerdeEvent::new("talkGroup", json!({
+                "chain": chain,
+                "talkgroup": tg,
+            })).write(s),
             UpdateSignalPower(p) => SerdeEvent::new("sigPower", p).write(s),
+            UpdateFreqOffset(chain, freq, f) => SerdeEvent::new("freqOffset", json!({
+                "chain": chain,
+                "offset": f,
+                "ppm": ppm(f, freq),
+            })).write(s),
+            UpdateQuality(freq, q) => SerdeEvent::new("quality", json!({
+                "freq": freq,
+                "dbfs": q.level.dbfs,
+                "snr": q.level.snr,
+                "deviation": q.deviation,
+                "deviationError": q.deviation_error,
+                "symbolErrors": q.symbol_errors,
+            })).write(s),
+            UpdateSymbols(ref snap) => SerdeEvent::new("symbols", json!({
+                "phase": snap.phase,
+                "symbols": &snap.symbols,
+                "thresholds": &snap.thresholds[..],
+                "eye": &snap.eye,
+            })).write(s),
+            UpdateSpectrum(rate, offset, ref power) => SerdeEvent::new("spectrum", json!({
+                "rate": rate,
+                "offset": offset,
+                "power": power,
+            })).write(s),
+            UpdateDeviceStatus(dev, connected) => SerdeEvent::new("deviceStatus", json!({
+                "device": dev,
+                "connected": connected,
+            })).write(s),
             // If this event has been received, the TSBK is valid with a known opcode.
             TrunkingControl(tsbk) => match tsbk.opcode().unwrap() {
                 TsbkOpcode::RfssStatusBroadcast => stream_rfss_status(s,
@@ -381,6 +597,7 @@ impl HubTask {
                 },
                 _ => Ok(()),
             },
+            MotorolaControl(op, tsbk) => self.stream_motorola(s, op, tsbk),
             // If this event has been received, the LC has a known opcode.
             LinkControl(lc) => match lc.opcode().unwrap() {
                 LinkControlOpcode::GroupVoiceTraffic =>
@@ -396,11 +613,72 @@ impl HubTask {
                     fields::AltControlChannel::new(lc.payload())),
                 _ => Ok(()),
             },
-            UpdateStats(stats) =>
-                SerdeEvent::new("updateStats", serialize_stats(&stats)).write(s),
+            UpdateStats(stats, pipeline) => SerdeEvent::new("updateStats",
+                serialize_stats(&stats, &pipeline)).write(s),
+        }
+    }
+
+    fn stream_motorola(&self, mut s: &mut TcpStream, op: MotorolaOpcode,
+                       tsbk: TsbkFields)
+        -> Result<(), ()>
+    {
+        match op {
+            op @ MotorolaOpcode::PatchAdd | op @ MotorolaOpcode::PatchDelete => {
+                let p = motorola::PatchGroups::new(tsbk.payload());
+
+                SerdeEvent::new("motPatch", json!({
+                    "supergroup": p.supergroup(),
+                    "groups": p.groups(),
+                    "active": op == MotorolaOpcode::PatchAdd,
+                })).write(s)
+            },
+            MotorolaOpcode::PatchGrant => {
+                let f = motorola::PatchGrant::new(tsbk.payload());
+
+                let freq = match self.lookup_freq(f.channel()) {
+                    Some(f) => f,
+                    None => return Ok(()),
+                };
+
+                SerdeEvent::new("motPatchGrant", json!({
+                    "supergroup": f.supergroup(),
+                    "unit": f.src_unit(),
+                    "freq": freq,
+                })).write(s)
+            },
+            MotorolaOpcode::PatchGrantUpdate => {
+                let f = motorola::PatchGrantUpdate::new(tsbk.payload());
+
+                for &(ch, sg) in f.updates().iter() {
+                    let freq = match self.lookup_freq(ch) {
+                        Some(f) if sg != 0 => f,
+                        _ => continue,
+                    };
+
+                    SerdeEvent::new("motPatchUpdate", json!({
+                        "supergroup": sg,
+                        "freq": freq,
+                    })).write(&mut s)?;
+                }
+
+                Ok(())
+            },
+            MotorolaOpcode::BaseStationId => {
+                let f = motorola::BaseStationId::new(tsbk.payload());
+
+                SerdeEvent::new("motBaseStation", json!({
+                    "callsign": f.callsign(),
+                    "freq": self.lookup_freq(f.channel()),
+                })).write(s)
+            },
         }
     }
 
+    /// Look up the frequency (Hz) of the given channel in the current channel plan.
+    fn lookup_freq(&self, (id, number): ChannelRef) -> Option<u32> {
+        self.state.channels.lookup(id).map(|p| p.rx_freq(number))
+    }
+
     fn stream_alt_control(&self, mut s: &mut TcpStream, f: fields::AltControlChannel)
         -> Result<(), ()>
     {
@@ -445,18 +723,28 @@ impl HubTask {
 pub enum HubEvent {
     /// Some state update.
     State(StateEvent),
-    /// Center frequency was changed.
-    UpdateCurFreq(u32),
-    /// Current talkgroup has changed.
-    UpdateTalkGroup(u16),
     /// Power of received signal.
     UpdateSignalPower(f32),
+    /// Estimated carrier frequency offset (Hz) on the chain at the contained index,
+    /// with the SDR tuned to the contained frequency (Hz).
+    UpdateFreqOffset(usize, u32, f32),
+    /// Quality of the signal received on the contained frequency (Hz).
+    UpdateQuality(u32, SignalQuality),
+    /// Symbols recovered from the first chain.
+    UpdateSymbols(Snapshot),
+    /// Power (dBFS) in each frequency bin of I/Q sampled at the contained rate
+    /// (samples/sec), with the SDR tuned the contained offset (Hz) from the channel.
+    UpdateSpectrum(u32, i32, Vec<f32>),
+    /// Device at the contained position was lost (false) or reopened (true).
+    UpdateDeviceStatus(usize, bool),
     /// Trunking control packet was received.
     TrunkingControl(TsbkFields),
     /// Link control packet was received.
     LinkControl(LinkControlFields),
-    /// Updated stat counters.
-    UpdateStats(Stats),
+    /// Motorola trunking control packet with the contained opcode was received.
+    MotorolaControl(MotorolaOpcode, TsbkFields),
+    /// Updated decoding and pipeline stat counters.
+    UpdateStats(Stats, PipelineStats),
 }
 
 /// State update events.
@@ -468,6 +756,10 @@ pub enum StateEvent {
     UpdateChannelParams(TsbkFields),
     /// Encrypted talkgroup encountered.
     UpdateEncrypted(u16, CryptoAlgorithm),
+    /// Chain at the contained index was moved to the contained frequency (Hz).
+    UpdateCurFreq(usize, u32),
+    /// Chain at the contained index started following the contained talkgroup.
+    UpdateTalkGroup(usize, u16),
 }
 
 /// Holds a copy of certain state held in other tasks.
@@ -478,6 +770,16 @@ pub struct State {
     channels: ChannelParamsMap,
     /// Known encrypted talkgroups.
     encrypted: GroupCryptoMap,
+    /// Current settings of each tuner.
+    settings: Vec<TunerSettings>,
+    /// Fixed gains supported by the control channel tuner.
+    gains: Vec<i32>,
+    /// Current demodulator modulation.
+    modulation: Modulation,
+    /// Current demodulator profile.
+    profile: DemodProfile,
+    /// Current frequency and talkgroup of each chain, with the control chain first.
+    chains: Vec<SerdeChain>,
 }
 
 impl Default for State {
@@ -486,6 +788,11 @@ impl Default for State {
             ctlfreq: std::u32::MAX,
             channels: ChannelParamsMap::default(),
             encrypted: GroupCryptoMap::default(),
+            settings: vec![],
+            gains: vec![],
+            modulation: Modulation::C4fm,
+            profile: DemodProfile::default(),
+            chains: vec![],
         }
     }
 }
@@ -500,8 +807,19 @@ impl State {
             UpdateChannelParams(tsbk) =>
                 self.channels.update(&fields::ChannelParamsUpdate::new(tsbk.payload())),
             UpdateEncrypted(tg, alg) => { self.encrypted.insert(tg, alg); },
+            UpdateCurFreq(chain, f) => self.chain(chain).freq = Some(f),
+            UpdateTalkGroup(chain, tg) => self.chain(chain).talkgroup = Some(tg),
         }
     }
+
+    /// Get the state of the given chain, tracking any chains not yet seen.
+    fn chain(&mut self, chain: usize) -> &mut SerdeChain {
+        if chain >= self.chains.len() {
+            self.chains.resize(chain + 1, SerdeChain::default());
+        }
+
+        &mut self.chains[chain]
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -509,6 +827,44 @@ struct SerdeCtlFreq {
     ctlfreq: u32,
 }
 
+#[derive(Serialize, Clone, Default)]
+struct SerdeChain {
+    /// Frequency (Hz) the chain is tuned to, if it's been tuned yet.
+    freq: Option<u32>,
+    /// Talkgroup the chain last followed, if any.
+    talkgroup: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct SerdeGain {
+    /// Tuner to change, with the control channel device at 0.
+    #[serde(default)]
+    device: usize,
+    /// Fixed gain (tenths of a dB), or `null` for automatic gain control.
+    gain: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct SerdePpm {
+    /// Tuner to change, with the control channel device at 0.
+    #[serde(default)]
+    device: usize,
+    /// Frequency correction (PPM).
+    ppm: i32,
+}
+
+#[derive(Deserialize)]
+struct SerdeDemod {
+    /// Modulation name, such as `c4fm` or `cqpsk`.
+    demod: String,
+}
+
+#[derive(Deserialize)]
+struct SerdeProfile {
+    /// Profile name, such as `p25` or `narrow`.
+    profile: String,
+}
+
 #[derive(Serialize)]
 struct SerdeEvent<T: Serialize> {
     event: &'static str,
@@ -531,6 +887,16 @@ impl<T: Serialize> SerdeEvent<T> {
     }
 }
 
+/// Convert the given carrier offset (Hz) into a frequency error (PPM) relative to the
+/// given tuned frequency (Hz), if it's known.
+fn ppm(offset: f32, freq: u32) -> Option<f32> {
+    if freq == 0 {
+        None
+    } else {
+        Some(offset / freq as f32 * 1e6)
+    }
+}
+
 fn stream_rfss_status(s: &mut TcpStream, f: fields::RfssStatusBroadcast)
     -> Result<(), ()>
 {
@@ -552,8 +918,9 @@ fn stream_net_status(s: &mut TcpStream, f: fields::NetworkStatusBroadcast)
     })).write(s)
 }
 
-fn serialize_stats(s: &Stats) -> impl Serialize {
+fn serialize_stats(s: &Stats, p: &PipelineStats) -> impl Serialize {
     json!({
+        "pipeline": serialize_pipeline_stats(p),
         "bch": serialize_code_stats(&s.bch),
         "cyclic": serialize_code_stats(&s.cyclic),
         "golayStd": serialize_code_stats(&s.golay_std),
@@ -569,6 +936,24 @@ fn serialize_stats(s: &Stats) -> impl Serialize {
     })
 }
 
+fn serialize_pipeline_stats(s: &PipelineStats) -> impl Serialize {
+    json!({
+        "sdrDrops": s.sdr_drops,
+        "basebandDrops": s.baseband_drops,
+        "audioDrops": s.audio_drops,
+        "iqBacklog": serialize_backlog_stats(&s.iq),
+        "basebandBacklog": serialize_backlog_stats(&s.baseband),
+        "audioBacklog": serialize_backlog_stats(&s.audio),
+    })
+}
+
+fn serialize_backlog_stats(s: &BacklogStats) -> impl Serialize {
+    json!({
+        "cur": s.cur,
+        "max": s.max,
+    })
+}
+
 fn serialize_code_stats(s: &CodeStats) -> impl Serialize {
     json!({
         "totalWords": s.words,
@@ -0,0 +1,124 @@
+//! Correction of DC offset and I/Q imbalance from the SDR.
+
+use num::complex::Complex32;
+use num::traits::Zero;
+use slice_mip::MapInPlace;
+
+/// Fraction of each chunk's measurements blended into the running estimates.
+const CORRECTION_GAIN: f32 = 0.1;
+
+/// Adaptively removes the DC offset and I/Q gain and phase imbalance from I/Q signal.
+///
+/// Imbalance mixes a little of the conjugate into the signal, which shows up as an image
+/// mirrored about the center frequency. Received signals are otherwise proper, with a
+/// vanishing mean square, so the conjugate is added back scaled to cancel the measured
+/// mean square, which removes the image to first order.
+#[derive(Copy, Clone)]
+pub struct IqCorrection {
+    /// Estimated DC offset.
+    dc: Complex32,
+    /// Running mean square of the signal without DC.
+    square: Complex32,
+    /// Running mean power of the signal without DC.
+    power: f32,
+}
+
+impl Default for IqCorrection {
+    fn default() -> Self {
+        IqCorrection {
+            dc: Complex32::zero(),
+            square: Complex32::zero(),
+            power: 0.0,
+        }
+    }
+}
+
+impl IqCorrection {
+    /// Update the estimates with the given chunk of samples and correct them in place.
+    pub fn correct(&mut self, samples: &mut [Complex32]) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let len = samples.len() as f32;
+
+        // Estimates start from nothing rather than the first chunk, so a short stretch
+        // of atypical signal can't skew them.
+        let mean = samples.iter().fold(Complex32::zero(), |s, &x| s + x) / len;
+        self.dc = self.dc + (mean - self.dc) * CORRECTION_GAIN;
+
+        let dc = self.dc;
+
+        let (square, power) = samples.iter().map(|&s| s - dc)
+            .fold((Complex32::zero(), 0.0), |(sq, p), x| (sq + x * x, p + x.norm_sqr()));
+
+        self.square = self.square + (square / len - self.square) * CORRECTION_GAIN;
+        self.power += (power / len - self.power) * CORRECTION_GAIN;
+
+        let weight = self.weight();
+
+        samples.map_in_place(|&x| {
+            let x = x - dc;
+            x + weight * x.conj()
+        });
+    }
+
+    /// Calculate the weight of the conjugate added to cancel the image.
+    fn weight(&self) -> Complex32 {
+        // With the signal z + e*conj(z), the mean square is about 2e times the power.
+        if self.power > 0.0 {
+            -self.square / (2.0 * self.power)
+        } else {
+            Complex32::zero()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// Correlate the given samples with a tone at the given frequency (cycles/sample).
+    fn tone(samples: &[Complex32], freq: f32) -> f32 {
+        samples.iter().enumerate().fold(Complex32::zero(), |s, (n, &x)| {
+            s + x * Complex32::from_polar(1.0, -2.0 * PI * freq * n as f32)
+        }).norm() / samples.len() as f32
+    }
+
+    #[test]
+    fn test_correction() {
+        let imbalance = Complex32::new(0.08, -0.06);
+        let dc = Complex32::new(0.05, -0.02);
+
+        let chunk = || -> Vec<Complex32> {
+            (0..1000).map(|n| {
+                let z = Complex32::from_polar(0.5, 2.0 * PI * 0.1 * n as f32);
+                z + imbalance * z.conj() + dc
+            }).collect()
+        };
+
+        // The image sits 20dB below the tone.
+        let samples = chunk();
+        assert!((tone(&samples[..], -0.1) / tone(&samples[..], 0.1) - 0.1).abs() < 1e-3);
+
+        let mut c = IqCorrection::default();
+
+        for _ in 0..100 {
+            c.correct(&mut chunk()[..]);
+        }
+
+        let mut samples = chunk();
+        c.correct(&mut samples[..]);
+
+        assert!((tone(&samples[..], 0.1) - 0.5).abs() < 0.01);
+        assert!(tone(&samples[..], -0.1) / tone(&samples[..], 0.1) < 0.01);
+        assert!(tone(&samples[..], 0.0) < 1e-3);
+
+        // Nothing to measure in silence.
+        let mut c = IqCorrection::default();
+        let mut samples = vec![Complex32::zero(); 100];
+        c.correct(&mut samples[..]);
+        assert!(samples.iter().all(|s| s.norm() == 0.0));
+    }
+}
@@ -0,0 +1,232 @@
+//! Raw I/Q sample files.
+
+use std::io::{Read, Write, ErrorKind};
+use std::str::FromStr;
+use std;
+
+use consts::{BUF_BYTES, BUF_SAMPLES};
+use sdr::SampleSource;
+
+/// Sample format of an I/Q file.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum IqFormat {
+    /// Interleaved unsigned 8-bit samples, as written by `rtl_sdr`.
+    Cu8,
+    /// Interleaved little-endian 32-bit float samples.
+    Cf32,
+}
+
+impl FromStr for IqFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cu8" => Ok(IqFormat::Cu8),
+            "cf32" => Ok(IqFormat::Cf32),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Reads I/Q samples from a stream as fast as the receiver can consume them.
+///
+/// The pipeline carries 8-bit samples, like those of an RTL-SDR, so float samples are
+/// requantized to 8 bits. This loses any resolution and dynamic range beyond what an
+/// RTL-SDR captures: samples are scaled and then clamped to [-1, 1], and anything
+/// smaller than a 256th of full scale is lost.
+pub struct IqFileSource<R: Read + Send> {
+    /// Stream to read from.
+    stream: R,
+    /// Sample format of the stream.
+    fmt: IqFormat,
+    /// Factor applied to float samples before they're quantized.
+    scale: f32,
+    /// Whether any float samples have been clipped.
+    clipped: bool,
+}
+
+impl<R: Read + Send> IqFileSource<R> {
+    /// Create a new `IqFileSource` reading samples of the given format from the given
+    /// stream, multiplying float samples by the given factor before quantizing them.
+    pub fn new(stream: R, fmt: IqFormat, scale: f32) -> Self {
+        IqFileSource {
+            stream: stream,
+            fmt: fmt,
+            scale: scale,
+            clipped: false,
+        }
+    }
+
+    /// Fill the given buffer with the next chunk of 8-bit samples.
+    ///
+    /// Return `Ok(false)` if the stream has no more whole chunks.
+    fn read_chunk(&mut self, buf: &mut [u8], floats: &mut [u8]) -> std::io::Result<bool> {
+        let res = match self.fmt {
+            IqFormat::Cu8 => self.stream.read_exact(buf),
+            IqFormat::Cf32 => self.stream.read_exact(floats),
+        };
+
+        match res {
+            Ok(()) => {},
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        if let IqFormat::Cf32 = self.fmt {
+            for (b, f) in buf.iter_mut().zip(floats.chunks(4)) {
+                let s = f32::from_le_bytes([f[0], f[1], f[2], f[3]]) * self.scale;
+
+                if s.abs() > 1.0 && !self.clipped {
+                    warn!("I/Q samples exceed full scale and are clipped (try a lower \
+                           --iq-scale)");
+                    self.clipped = true;
+                }
+
+                *b = quantize(s);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read + Send> SampleSource for IqFileSource<R> {
+    fn read(&mut self, cb: &mut dyn FnMut(&[u8])) -> std::io::Result<()> {
+        let mut buf = vec![0; BUF_BYTES];
+        let mut floats = match self.fmt {
+            IqFormat::Cu8 => vec![],
+            IqFormat::Cf32 => vec![0; BUF_SAMPLES * 2 * 4],
+        };
+
+        while self.read_chunk(&mut buf[..], &mut floats[..])? {
+            cb(&buf[..]);
+        }
+
+        Ok(())
+    }
+
+    fn realtime(&self) -> bool { false }
+}
+
+/// Records raw 8-bit I/Q samples along with a marker for each retune.
+///
+/// Samples are written in the same format read by `IqFileSource`. Each marker is written
+/// as a separate line holding the index of the first sample received after the retune
+/// and the new center frequency (Hz).
+pub struct IqRecorder<W: Write + Send> {
+    /// Stream for samples.
+    samples: W,
+    /// Stream for retune markers.
+    markers: W,
+    /// Number of samples recorded so far.
+    pos: u64,
+}
+
+impl<W: Write + Send> IqRecorder<W> {
+    /// Create a new `IqRecorder` writing samples and markers to the given streams.
+    pub fn new(samples: W, markers: W) -> Self {
+        IqRecorder {
+            samples: samples,
+            markers: markers,
+            pos: 0,
+        }
+    }
+
+    /// Record the given chunk of interleaved samples.
+    pub fn record(&mut self, bytes: &[u8]) {
+        self.samples.write_all(bytes).expect("unable to write I/Q samples");
+        self.pos += bytes.len() as u64 / 2;
+    }
+
+    /// Record a retune to the given frequency (Hz) at the current sample.
+    pub fn retune(&mut self, freq: u32) {
+        writeln!(self.markers, "{} {}", self.pos, freq)
+            .and_then(|_| self.markers.flush())
+            .expect("unable to write I/Q marker");
+    }
+}
+
+/// Convert the given float sample in [-1, 1] to the nearest 8-bit RTL-SDR sample.
+fn quantize(s: f32) -> u8 {
+    (s * 127.5 + 127.5).round().max(0.0).min(255.0) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quantize() {
+        assert_eq!(quantize(-1.0), 0);
+        assert_eq!(quantize(1.0), 255);
+        assert_eq!(quantize(0.0), 128);
+        assert_eq!(quantize(-0.001), 127);
+        assert_eq!(quantize(-4.0), 0);
+        assert_eq!(quantize(4.0), 255);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut bytes = vec![];
+
+        for i in 0..BUF_SAMPLES * 2 * 3 {
+            let s: f32 = if i % 2 == 0 { 1.0 } else { -1.0 };
+            bytes.extend_from_slice(&s.to_le_bytes());
+        }
+
+        // Add a partial trailing chunk, which should be dropped.
+        bytes.extend_from_slice(&[0; 12]);
+
+        let mut src = IqFileSource::new(&bytes[..], IqFormat::Cf32, 1.0);
+        let mut chunks = 0;
+
+        src.read(&mut |buf: &[u8]| {
+            assert_eq!(buf.len(), BUF_BYTES);
+            assert!(buf.chunks(2).all(|p| p == &[255, 0]));
+            chunks += 1;
+        }).unwrap();
+
+        assert_eq!(chunks, 3);
+        assert!(!src.clipped);
+
+        // Scaled samples are clamped to full scale.
+        let mut src = IqFileSource::new(&bytes[..], IqFormat::Cf32, 0.5);
+
+        src.read(&mut |buf: &[u8]| {
+            assert!(buf.chunks(2).all(|p| p == &[191, 64]));
+        }).unwrap();
+
+        let mut src = IqFileSource::new(&bytes[..], IqFormat::Cf32, 2.0);
+
+        src.read(&mut |buf: &[u8]| {
+            assert!(buf.chunks(2).all(|p| p == &[255, 0]));
+        }).unwrap();
+
+        assert!(src.clipped);
+
+        let bytes = vec![42; BUF_BYTES * 2 + 1];
+        let mut src = IqFileSource::new(&bytes[..], IqFormat::Cu8, 1.0);
+        let mut chunks = 0;
+
+        src.read(&mut |buf: &[u8]| {
+            assert!(buf.iter().all(|&b| b == 42));
+            chunks += 1;
+        }).unwrap();
+
+        assert_eq!(chunks, 2);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut rec = IqRecorder::new(vec![], vec![]);
+
+        rec.retune(851000000);
+        rec.record(&[1, 2, 3, 4]);
+        rec.record(&[5, 6]);
+        rec.retune(852000000);
+        rec.record(&[7, 8]);
+
+        assert_eq!(&rec.samples[..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&rec.markers[..], &b"0 851000000\n3 852000000\n"[..]);
+    }
+}
@@ -23,6 +23,7 @@ extern crate num;
 extern crate p25;
 extern crate p25_filts;
 extern crate pool;
+extern crate rand;
 extern crate rtlsdr_iq;
 extern crate rtlsdr_mt;
 extern crate serde;
@@ -40,33 +41,57 @@ extern crate uhttp_uri;
 extern crate uhttp_version;
 
 use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
-use std::sync::mpsc::channel;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, sync_channel};
 
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches};
 use env_logger::{Builder, Env};
 use log::LevelFilter;
-use rtlsdr_mt::TunerGains;
 
 mod audio;
+mod channelizer;
 mod consts;
+mod cqpsk;
 mod demod;
+mod filter;
 mod http;
 mod hub;
+mod imbalance;
+mod iqfile;
+#[cfg(test)]
+mod modulator;
+mod motorola;
+mod nco;
+mod pipeline;
 mod policy;
+mod profile;
+mod quality;
 mod recv;
 mod replay;
+mod rtltcp;
+mod scope;
 mod sdr;
+#[cfg(test)]
+mod simulator;
+mod source;
+mod spectrum;
 mod talkgroups;
 
 use audio::{AudioOutput, AudioTask};
+use channelizer::ChannelizerTask;
 use consts::{BASEBAND_SAMPLE_RATE, SDR_SAMPLE_RATE};
-use demod::DemodTask;
+use demod::{DemodTask, Modulation};
 use hub::HubTask;
+use iqfile::{IqFileSource, IqFormat, IqRecorder};
+use pipeline::{Pipeline, IQ_BACKLOG};
 use policy::ReceiverPolicy;
+use profile::DemodProfile;
 use recv::RecvTask;
 use replay::ReplayReceiver;
-use sdr::{ReadTask, ControlTask};
+use sdr::{ReadTask, ControlTask, ControlTaskEvent, SampleSource, Tuner, TunerSettings,
+          RtlSource, RtlTuner, Open, Reconnect, sdr_error};
+use source::{NoiseSource, NullTuner};
 use talkgroups::TalkgroupSelection;
 
 fn main() {
@@ -77,7 +102,7 @@ fn main() {
             .multiple(true))
         .arg(Arg::with_name("ppm")
              .short('p')
-             .help("ppm frequency adjustment")
+             .help("ppm frequency adjustment (comma-separated to give each device its own)")
              .default_value("0")
              .value_name("PPM"))
         .arg(Arg::with_name("audio")
@@ -96,15 +121,52 @@ fn main() {
              .short('w')
              .help("write baseband samples to FILE (f32le/48kHz/mono)")
              .value_name("FILE"))
+        .arg(Arg::with_name("writeiq")
+             .long("write-iq")
+             .help("write raw I/Q samples to FILE (cu8) and retune markers to FILE.tune")
+             .value_name("FILE"))
         .arg(Arg::with_name("freq")
              .short('f')
-             .help("frequency for initial control channel (Hz)")
-             .value_name("FREQ"))
+             .help("frequency for initial control channel (Hz), or a comma-separated list \
+                    of candidates to hunt through")
+             .value_name("FREQ[,FREQ...]"))
         .arg(Arg::with_name("device")
              .short('d')
-             .help("rtlsdr device index (use -d list to show all)")
+             .help("rtlsdr device indexes, the first for the control channel and any others \
+                    for traffic channels (use -d list to show all)")
              .default_value("0")
-             .value_name("INDEX"))
+             .value_name("INDEX[,INDEX...]"))
+        .arg(Arg::with_name("noise")
+             .long("noise")
+             .help("use a synthetic noise source instead of an rtlsdr"))
+        .arg(Arg::with_name("rtltcp")
+             .long("rtltcp")
+             .help("receive from the rtl_tcp server at ADDR (host:port) instead of an rtlsdr")
+             .value_name("ADDR"))
+        .arg(Arg::with_name("iq")
+             .long("iq")
+             .help("demodulate raw I/Q samples from FILE instead of an rtlsdr")
+             .value_name("FILE"))
+        .arg(Arg::with_name("iqformat")
+             .long("iq-format")
+             .help("sample format of the --iq file (cu8, cf32)")
+             .default_value("cu8")
+             .value_name("FORMAT"))
+        .arg(Arg::with_name("iqscale")
+             .long("iq-scale")
+             .help("factor applied to cf32 samples before they're clamped to 8 bits")
+             .default_value("1.0")
+             .value_name("SCALE"))
+        .arg(Arg::with_name("demod")
+             .long("demod")
+             .help("modulation of the received signal (c4fm, cqpsk)")
+             .default_value("c4fm")
+             .value_name("MOD"))
+        .arg(Arg::with_name("profile")
+             .long("profile")
+             .help("demodulator profile (p25, narrow, wide, raw)")
+             .default_value("p25")
+             .value_name("NAME"))
         .arg(Arg::with_name("bind")
              .short('b')
              .help("HTTP socket bind address")
@@ -114,6 +176,31 @@ fn main() {
              .short('n')
              .long("nohop")
              .help("disable frequency hopping"))
+        .arg(Arg::with_name("rate")
+             .short('s')
+             .long("rate")
+             .help("SDR sample rate (samples/sec), a multiple of 48000 [default: 240000]")
+             .conflicts_with("wideband")
+             .value_name("RATE"))
+        .arg(Arg::with_name("offset")
+             .long("offset")
+             .help("tune the SDR OFFSET Hz away from each channel to avoid the DC spike")
+             .allow_hyphen_values(true)
+             .conflicts_with("wideband")
+             .value_name("OFFSET"))
+        .arg(Arg::with_name("wideband")
+             .long("wideband")
+             .help("capture RATE samples/sec and demodulate channels without retuning")
+             .value_name("RATE"))
+        .arg(Arg::with_name("center")
+             .long("center")
+             .help("center frequency (Hz) of wideband capture [default: control channel]")
+             .value_name("FREQ"))
+        .arg(Arg::with_name("chains")
+             .long("traffic-chains")
+             .help("number of traffic channels demodulated at once in wideband mode")
+             .default_value("2")
+             .value_name("NUM"))
         .arg(Arg::with_name("pause")
              .long("pause-timeout")
              .help("time (sec) to wait for voice message to be resumed")
@@ -124,6 +211,21 @@ fn main() {
              .help("time (sec) to wait for voice message to begin")
              .default_value("2.0")
              .value_name("TIME"))
+        .arg(Arg::with_name("squelch")
+             .long("squelch")
+             .help("lowest channel SNR (dB) considered to hold a carrier")
+             .allow_hyphen_values(true)
+             .value_name("DB"))
+        .arg(Arg::with_name("minsnr")
+             .long("min-snr")
+             .help("lowest SNR (dB) tolerated before leaving a traffic channel")
+             .allow_hyphen_values(true)
+             .value_name("DB"))
+        .arg(Arg::with_name("hunt")
+             .long("hunt-timeout")
+             .help("time (sec) without control channel activity before hunting for another")
+             .default_value("3.0")
+             .value_name("TIME"))
         .arg(Arg::with_name("tgselect")
              .long("tgselect-timeout")
              .help("time (sec) to collect talkgroups before making a selection")
@@ -164,51 +266,135 @@ fn main() {
         return;
     }
 
-    let ppm: i32 = args.value_of("ppm").unwrap().parse()
-        .expect("invalid ppm");
-
     let samples_file = args.value_of("write")
         .map(|path| File::create(path).expect("unable to open baseband file"));
 
-    let dev: u32 = match args.value_of("device").unwrap() {
-        "list" => {
-            for (idx, name) in rtlsdr_mt::devices().enumerate() {
-                println!("{}: {}", idx, name.to_str().unwrap());
-            }
+    let iq_rec = args.value_of("writeiq").map(|path| {
+        info!("writing I/Q samples to {}", path);
 
-            return;
-        },
-        s => s.parse().expect("invalid device index"),
-    };
+        IqRecorder::new(
+            BufWriter::new(File::create(path).expect("unable to open I/Q file")),
+            BufWriter::new(File::create(format!("{}.tune", path))
+                .expect("unable to open I/Q marker file")),
+        )
+    });
 
-    info!("opening RTL-SDR at index {}", dev);
-    let (mut control, reader) = rtlsdr_mt::open(dev)
-        .expect("unable to open rtlsdr");
+    let wideband: Option<u32> = args.value_of("wideband").map(|r| {
+        let rate = r.parse().expect("invalid wideband rate");
 
-    match args.value_of("gain").expect("-g option is required") {
-        "list" => {
-            let mut gains = TunerGains::default();
+        if rate % (BASEBAND_SAMPLE_RATE * 2) != 0 {
+            panic!("wideband rate must be a multiple of {}", BASEBAND_SAMPLE_RATE * 2);
+        }
 
-            for g in control.tuner_gains(&mut gains) {
-                println!("{}", g);
-            }
+        if !rate_supported(rate) {
+            panic!("wideband rate {} is not supported by the RTL-SDR", rate);
+        }
 
-            println!("auto");
+        rate
+    });
 
-            return;
-        },
-        "auto" => {
-            info!("enabling hardware AGC");
-            control.enable_agc().expect("unable to enable agc");
+    let rate = match wideband {
+        Some(rate) => rate,
+        None => match args.value_of("rate") {
+            Some(r) => {
+                let rate = r.parse().expect("invalid sample rate");
+
+                if rate % BASEBAND_SAMPLE_RATE != 0 {
+                    panic!("sample rate must be a multiple of {}", BASEBAND_SAMPLE_RATE);
+                }
+
+                if !rate_supported(rate) {
+                    panic!("sample rate {} is not supported by the RTL-SDR", rate);
+                }
+
+                info!("using sample rate {} samples/sec", rate);
+                rate
+            },
+            None => SDR_SAMPLE_RATE,
         },
-        s => {
-            let gain = s.parse().expect("invalid gain");
-            info!("setting hardware gain to {:.1} dB", gain as f32 / 10.0);
-            control.set_tuner_gain(gain).expect("unable to set gain");
+    };
+
+    let offset: i32 = match args.value_of("offset") {
+        Some(o) => {
+            let offset: i32 = o.parse().expect("invalid offset");
+
+            // Leave room for the channel within the captured band.
+            if offset.abs() as u32 >= rate / 2 - BASEBAND_SAMPLE_RATE / 2 {
+                panic!("offset must be less than {} Hz", rate / 2 - BASEBAND_SAMPLE_RATE / 2);
+            }
+
+            info!("tuning {} Hz away from each channel", offset);
+            offset
         },
+        None => 0,
+    };
+
+    let mut devices: Vec<Device> =
+        if args.is_present("noise") {
+            info!("using synthetic noise source");
+
+            vec![Device {
+                tuner: Box::new(NullTuner),
+                source: Box::new(NoiseSource::new(rate)),
+                settings: TunerSettings::default(),
+                open: None,
+            }]
+        } else if let Some(path) = args.value_of("iq") {
+            let fmt: IqFormat = args.value_of("iqformat").unwrap().parse()
+                .expect("invalid I/Q format");
+            let scale: f32 = args.value_of("iqscale").unwrap().parse()
+                .expect("invalid I/Q scale");
+            let stream = File::open(path).expect("unable to open I/Q file");
+
+            info!("reading {:?} I/Q samples from {}", fmt, path);
+
+            vec![Device {
+                tuner: Box::new(NullTuner),
+                source: Box::new(IqFileSource::new(BufReader::new(stream), fmt, scale)),
+                settings: TunerSettings::default(),
+                open: None,
+            }]
+        } else if let Some(addr) = args.value_of("rtltcp") {
+            info!("connecting to rtl_tcp at {}", addr);
+            let (mut t, s) = rtltcp::connect(addr).expect("unable to connect to rtl_tcp");
+            let addr = addr.to_string();
+
+            vec![Device {
+                settings: configure_tuner(&mut t, &args, rate, 0),
+                tuner: Box::new(t),
+                source: Box::new(s),
+                open: Some(Box::new(move || {
+                    rtltcp::connect(&addr[..]).map(|(t, s)| {
+                        (Box::new(t) as Box<dyn Tuner>, Box::new(s) as Box<dyn SampleSource>)
+                    })
+                })),
+            }]
+        } else {
+            match open_rtlsdr(&args) {
+                Some(devs) => devs.into_iter().enumerate().map(|(dev, (idx, mut t, s))| {
+                    Device {
+                        settings: configure_tuner(&mut t, &args, rate, dev),
+                        tuner: Box::new(t),
+                        source: Box::new(s),
+                        open: Some(Box::new(move || {
+                            rtlsdr_mt::open(idx).map(|(control, reader)| {
+                                (Box::new(RtlTuner::new(control)) as Box<dyn Tuner>,
+                                 Box::new(RtlSource::new(reader)) as Box<dyn SampleSource>)
+                            }).map_err(|_| sdr_error("unable to open rtlsdr"))
+                        })),
+                    }
+                }).collect(),
+                None => return,
+            }
+        };
+
+    if wideband.is_some() && devices.len() > 1 {
+        panic!("wideband mode supports only a single device");
     }
 
-    let hopping = !args.is_present("nohop");
+    // Recorded samples can only follow a retune within a wideband capture.
+    let hopping = !args.is_present("nohop") &&
+        (!args.is_present("iq") || wideband.is_some());
 
     let pause = time_samples(args.value_of("pause").unwrap().parse()
         .expect("invalid pause timeout"));
@@ -217,52 +403,161 @@ fn main() {
     let tgselect = time_samples(args.value_of("tgselect").unwrap().parse()
         .expect("invalid tgselect timeout"));
 
-    info!("setting frequency offset to {} PPM", ppm);
-    control.set_ppm(ppm).expect("unable to set ppm");
-    control.set_sample_rate(SDR_SAMPLE_RATE).expect("unable to set sample rate");
+    let hunt = time_samples(args.value_of("hunt").unwrap().parse()
+        .expect("invalid hunt timeout"));
+
+    let squelch = args.value_of("squelch").map(|s| s.parse().expect("invalid squelch"));
+    let min_snr = args.value_of("minsnr").map(|s| s.parse().expect("invalid minimum SNR"));
+
+    let freqs: Vec<u32> = args.value_of("freq").expect("-f option is required")
+        .split(',').map(|f| f.parse().expect("invalid frequency")).collect();
+    let freq = freqs[0];
 
-    let freq: u32 = args.value_of("freq").expect("-f option is required")
-        .parse().expect("invalid frequency");
     info!("using control channel frequency {} Hz", freq);
 
+    if freqs.len() > 1 {
+        info!("hunting through {} candidate control channels", freqs.len());
+    }
+
     let addr = args.value_of("bind").unwrap().parse()
         .expect("invalid bind address");
 
-    let (tx_ctl, rx_ctl) = channel();
     let (tx_recv, rx_recv) = channel();
-    let (tx_read, rx_read) = channel();
     let (tx_audio, rx_audio) = channel();
     let (tx_hub, rx_hub) = mio_more::channel::channel();
 
-    let policy = ReceiverPolicy::new(tgselect, watchdog, pause);
+    let modulation: Modulation = args.value_of("demod").unwrap().parse()
+        .expect("invalid modulation");
+
+    if modulation != Modulation::C4fm {
+        info!("using {} demodulation", modulation.name());
+    }
+
+    let profile: DemodProfile = args.value_of("profile").unwrap().parse()
+        .expect("invalid demodulator profile");
+
+    if profile != DemodProfile::default() {
+        info!("using {} demodulator profile", profile.name);
+    }
+
+    let policy = ReceiverPolicy::new(tgselect, watchdog, pause, min_snr);
     let talkgroups = TalkgroupSelection::default();
+    let pipeline = Arc::new(Pipeline::default());
+
+    // Only the control channel device is queried for its supported gains.
+    let gains = devices[0].tuner.tuner_gains();
+
+    let mut iq_rec = iq_rec;
+    let mut controls = vec![];
+    let mut reads = vec![];
+    let mut tuners = vec![];
+    let mut readers = vec![];
+    let mut settings = vec![];
+
+    for (idx, dev) in devices.into_iter().enumerate() {
+        let (tx_ctl, rx_ctl) = channel();
+        let (tx_retune, rx_retune) = channel();
+        let (tx_read, rx_read) = sync_channel(IQ_BACKLOG);
+
+        let reconnect = dev.open.map(|open| {
+            Reconnect::new(idx, open, tx_ctl.clone(), tx_hub.clone())
+        });
+
+        controls.push(ControlTask::new(dev.tuner, rate, dev.settings, offset, rx_ctl,
+                                         tx_retune, idx, tx_recv.clone()));
+        // Only the control channel device is recorded.
+        reads.push((ReadTask::new(tx_read, rx_retune, iq_rec.take(), reconnect,
+                                  pipeline.clone()),
+                    dev.source));
+        tuners.push(tx_ctl);
+        readers.push(rx_read);
+        settings.push(dev.settings);
+    }
+
+    // Either give each SDR its own chain, the first of which hops around when it's the
+    // only one, or split a wideband capture into a control chain and several traffic
+    // chains.
+    let (sdrs, demods, channelizer, modems) = match wideband {
+        Some(rate) => {
+            let center = match args.value_of("center") {
+                Some(c) => c.parse().expect("invalid center frequency"),
+                None => freq,
+            };
+
+            let chains: usize = args.value_of("chains").unwrap().parse()
+                .expect("invalid number of traffic chains");
+
+            info!("capturing {} samples/sec around {} Hz with {} traffic chains",
+                rate, center, chains);
+
+            tuners[0].send(ControlTaskEvent::SetFreq(center, 0))
+                .expect("unable to set wideband frequency");
+
+            let (txs, rxs) = (0..chains + 1).map(|_| channel()).unzip();
+            let (modems, events) = (0..chains + 1).map(|_| channel()).unzip();
+
+            (txs, vec![], Some(ChannelizerTask::new(rate, center, modulation, profile,
+                squelch, readers.remove(0), rxs, events, tx_hub.clone(), tx_recv.clone(),
+                pipeline.clone())),
+                modems)
+        },
+        None => {
+            if tuners.len() > 1 {
+                info!("using {} traffic channel devices", tuners.len() - 1);
+            }
+
+            let (modems, events): (Vec<_>, Vec<_>) =
+                readers.iter().map(|_| channel()).unzip();
+
+            let demods = readers.into_iter().zip(events).enumerate()
+                .map(|(chain, (reader, events))| {
+                    DemodTask::new(chain, rate, offset, modulation, profile, squelch,
+                                   events, reader, tx_hub.clone(), tx_recv.clone(),
+                                   pipeline.clone())
+                }).collect();
+
+            (tuners.clone(), demods, None, modems)
+        },
+    };
 
     info!("starting HTTP server at http://{}", addr);
-    let mut hub = HubTask::new(rx_hub, tx_recv.clone(), &addr)
+    let mut hub = HubTask::new(rx_hub, tx_recv.clone(), tuners.clone(), settings, gains,
+                               modems, modulation, profile, &addr)
         .expect("unable to start hub");
-    let mut control = ControlTask::new(control, rx_ctl);
-    let mut read = ReadTask::new(tx_read);
-    let mut demod = DemodTask::new(rx_read, tx_hub.clone(), tx_recv.clone());
+
     let mut recv = RecvTask::new(rx_recv, tx_hub.clone(),
-        tx_ctl.clone(), tx_audio.clone(), freq, hopping, policy, talkgroups);
-    let mut audio = AudioTask::new(audio_out(), rx_audio);
+        sdrs, tx_audio.clone(), freqs, hunt, hopping, policy, talkgroups,
+        pipeline.clone());
+    let mut audio = AudioTask::new(audio_out(), rx_audio, pipeline);
 
     std::thread::scope(|scope| {
         scope.spawn(move || {
             hub.run();
         });
 
-        scope.spawn(move || {
-            control.run()
-        });
-
-        scope.spawn(move || {
-            read.run(reader);
-        });
-
-        scope.spawn(move || {
-            demod.run();
-        });
+        for mut control in controls {
+            scope.spawn(move || {
+                control.run()
+            });
+        }
+
+        for (mut read, source) in reads {
+            scope.spawn(move || {
+                read.run(source);
+            });
+        }
+
+        for mut demod in demods {
+            scope.spawn(move || {
+                demod.run();
+            });
+        }
+
+        if let Some(mut channelizer) = channelizer {
+            scope.spawn(move || {
+                channelizer.run();
+            });
+        }
 
         scope.spawn(move || {
             if let Some(mut f) = samples_file {
@@ -278,10 +573,109 @@ fn main() {
 
         scope.spawn(move || {
             audio.run();
+
+            // The remaining tasks run forever, so exit once the audio has been drained.
+            std::process::exit(0);
         });
     });
 }
 
+/// An opened SDR.
+struct Device {
+    /// Tunes the SDR.
+    tuner: Box<dyn Tuner>,
+    /// Reads samples from the SDR.
+    source: Box<dyn SampleSource>,
+    /// Settings initially applied to the tuner.
+    settings: TunerSettings,
+    /// Reopens the SDR if it's lost, if supported.
+    open: Option<Open>,
+}
+
+/// Open the RTL-SDRs selected by the given arguments, with the control channel device
+/// first, and return each with its device index.
+///
+/// Return `None` if the arguments only requested information to be listed.
+fn open_rtlsdr(args: &ArgMatches) -> Option<Vec<(u32, RtlTuner, RtlSource)>> {
+    let devs: Vec<u32> = match args.value_of("device").unwrap() {
+        "list" => {
+            for (idx, name) in rtlsdr_mt::devices().enumerate() {
+                println!("{}: {}", idx, name.to_str().unwrap());
+            }
+
+            return None;
+        },
+        s => s.split(',').map(|d| d.parse().expect("invalid device index")).collect(),
+    };
+
+    let mut opened = Vec::with_capacity(devs.len());
+
+    for dev in devs {
+        info!("opening RTL-SDR at index {}", dev);
+        let (control, reader) = rtlsdr_mt::open(dev)
+            .expect("unable to open rtlsdr");
+        let mut tuner = RtlTuner::new(control);
+
+        if let Some("list") = args.value_of("gain") {
+            for g in tuner.tuner_gains() {
+                println!("{}", g);
+            }
+
+            println!("auto");
+
+            return None;
+        }
+
+        opened.push((dev, tuner, RtlSource::new(reader)));
+    }
+
+    Some(opened)
+}
+
+/// Apply the gain and frequency correction selected by the given arguments for the
+/// given device position, as well as the given sample rate (samples/sec), to the given
+/// tuner, returning the applied settings.
+fn configure_tuner(tuner: &mut dyn Tuner, args: &ArgMatches, rate: u32, dev: usize)
+    -> TunerSettings
+{
+    let ppms: Vec<i32> = args.value_of("ppm").unwrap().split(',')
+        .map(|p| p.parse().expect("invalid ppm"))
+        .collect();
+
+    // Devices past the end of the list share the last correction.
+    let ppm = ppms[dev.min(ppms.len() - 1)];
+
+    let gain = match args.value_of("gain").expect("-g option is required") {
+        "auto" => {
+            info!("enabling hardware AGC");
+            tuner.enable_agc().expect("unable to enable agc");
+
+            None
+        },
+        s => {
+            let gain = s.parse().expect("invalid gain");
+            info!("setting hardware gain to {:.1} dB", gain as f32 / 10.0);
+            tuner.set_tuner_gain(gain).expect("unable to set gain");
+
+            Some(gain)
+        },
+    };
+
+    info!("setting frequency offset to {} PPM", ppm);
+    tuner.set_ppm(ppm).expect("unable to set ppm");
+    tuner.set_sample_rate(rate).expect("unable to set sample rate");
+
+    TunerSettings {
+        gain: gain,
+        ppm: ppm,
+    }
+}
+
+/// Check if the RTL-SDR can sample at the given rate (samples/sec).
+fn rate_supported(rate: u32) -> bool {
+    (rate > 225000 && rate <= 300000) || (rate > 900000 && rate <= 3200000)
+}
+
 /// Convert the given seconds into an amount of baseband samples.
 fn time_samples(t: f32) -> usize {
     (t * BASEBAND_SAMPLE_RATE as f32) as usize
@@ -0,0 +1,682 @@
+//! Synthetic P25 Phase 1 C4FM and CQPSK signal generation for exercising the receiver in
+//! tests.
+//!
+//! Data units are built up as streams of dibits, with frame sync, an encoded NID, and
+//! status symbols added around the body, then frequency or phase modulated into the
+//! 8-bit I/Q format produced by an RTL-SDR.
+
+use std::f64::consts::PI;
+use std;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use demod::DEVIATION;
+use filter;
+
+/// P25 symbol rate (symbols/sec).
+const SYMBOL_RATE: u32 = 4800;
+
+/// Frame synchronization word that begins every data unit.
+const FRAME_SYNC: u64 = 0x5575F5FF77FF;
+
+/// Generator polynomial of the (63, 16) BCH code protecting the NID.
+const BCH_GEN: u64 = 0o6331141367235453;
+
+/// Number of dibits between status symbols.
+const STATUS_PERIOD: usize = 35;
+
+/// Frequency deviation (Hz) of the outer symbols of a standard transmitter.
+const OUTER_DEVIATION: f32 = 1800.0;
+
+/// Rolloff of the root-raised-cosine pulse shaping CQPSK symbols.
+const CQPSK_ROLLOFF: f32 = 0.2;
+
+/// Number of symbol periods spanned by each CQPSK pulse.
+const CQPSK_SPAN: usize = 8;
+
+/// Amplitude of a lone CQPSK pulse at its peak, leaving headroom for overlapping pulses
+/// and noise.
+const CQPSK_AMPLITUDE: f32 = 0.35;
+
+/// Status symbol inserted into generated data units.
+const STATUS: u8 = 0b10;
+
+/// Number of coded body dibits in an LDU, excluding frame sync, NID, and status symbols.
+pub const LDU_DIBITS: usize = 784;
+
+/// Data unit ID of a trunking signaling packet.
+pub const DUID_TSBK: u8 = 0b0111;
+
+/// Data unit ID of an LDU1 voice frame group.
+pub const DUID_LDU1: u8 = 0b0101;
+
+/// Data unit ID of an LDU2 voice frame group.
+pub const DUID_LDU2: u8 = 0b1010;
+
+/// Data unit ID of a simple terminator.
+pub const DUID_TDU: u8 = 0b0011;
+
+/// Number of null body dibits in a simple terminator.
+const TDU_DIBITS: usize = 14;
+
+/// Number of dibits in each coded voice frame of an LDU.
+const VOICE_DIBITS: usize = 72;
+
+/// Number of dibits of low speed data in an LDU.
+const LSD_DIBITS: usize = 16;
+
+/// Primitive polynomial of the GF(2^6) field used by the Reed-Solomon codes.
+const GF_POLY: u8 = 0b1000011;
+
+/// Parity check rows of the shortened (10, 6, 3) Hamming code protecting each hexbit,
+/// giving the data bits covered by each parity bit, most significant first.
+const HAMMING_GEN: [u8; 4] = [0b111001, 0b110101, 0b101110, 0b011110];
+
+/// Constellation point produced by the half-rate trellis encoder for each state (the
+/// previous input dibit) and input dibit.
+const TRELLIS_STATES: [[usize; 4]; 4] = [
+    [0, 15, 12, 3],
+    [4, 11, 8, 7],
+    [13, 2, 1, 14],
+    [9, 6, 5, 10],
+];
+
+/// Dibit pair transmitted for each constellation point.
+const CONSTELLATION: [(u8, u8); 16] = [
+    (0b00, 0b10), (0b10, 0b10), (0b01, 0b11), (0b11, 0b11),
+    (0b11, 0b10), (0b01, 0b10), (0b10, 0b11), (0b00, 0b11),
+    (0b01, 0b01), (0b11, 0b01), (0b00, 0b00), (0b10, 0b00),
+    (0b10, 0b01), (0b00, 0b01), (0b11, 0b00), (0b01, 0b00),
+];
+
+/// Frequency or phase modulates dibits into RTL-SDR-style I/Q.
+pub struct Modulator {
+    /// I/Q sample rate (samples/sec).
+    rate: u32,
+    /// Frequency deviation (Hz) of the outer symbols.
+    deviation: f32,
+    /// Carrier offset (Hz) from the center frequency.
+    offset: f32,
+    /// Standard deviation of the gaussian noise added to each I/Q component, relative to
+    /// the signal amplitude.
+    noise: f32,
+    /// Current carrier phase (radians).
+    phase: f64,
+    /// Noise generator, seeded so signals are reproducible.
+    rng: StdRng,
+}
+
+impl Modulator {
+    /// Create a new `Modulator` producing I/Q at the given rate (samples/sec), which must
+    /// be a multiple of the symbol rate, with the given outer symbol deviation (Hz),
+    /// carrier offset (Hz), and relative noise level.
+    pub fn new(rate: u32, deviation: f32, offset: f32, noise: f32) -> Self {
+        assert!(rate % SYMBOL_RATE == 0);
+
+        Modulator {
+            rate: rate,
+            deviation: deviation,
+            offset: offset,
+            noise: noise,
+            phase: 0.0,
+            rng: StdRng::seed_from_u64(25),
+        }
+    }
+
+    /// Modulate the given dibits into interleaved 8-bit unsigned I/Q bytes.
+    pub fn modulate(&mut self, dibits: &[u8]) -> Vec<u8> {
+        let sps = (self.rate / SYMBOL_RATE) as usize;
+        let mut bytes = Vec::with_capacity(dibits.len() * sps * 2);
+
+        for &d in dibits.iter() {
+            let freq = self.offset + symbol(d) * self.deviation / 3.0;
+            let step = 2.0 * PI * freq as f64 / self.rate as f64;
+
+            for _ in 0..sps {
+                self.phase = (self.phase + step) % (2.0 * PI);
+
+                // Leave headroom below full scale for the noise.
+                let i = 0.5 * self.phase.cos() as f32 + self.gaussian();
+                let q = 0.5 * self.phase.sin() as f32 + self.gaussian();
+
+                bytes.push(quantize(i));
+                bytes.push(quantize(q));
+            }
+        }
+
+        bytes
+    }
+
+    /// Modulate the given dibits as π/4-shifted differential QPSK, as simulcast systems
+    /// transmit, into interleaved 8-bit unsigned I/Q bytes.
+    ///
+    /// Each symbol rotates the carrier phase by π/4 times its C4FM level, shaped by a
+    /// root-raised-cosine pulse, so the outer symbol deviation is unused.
+    pub fn modulate_cqpsk(&mut self, dibits: &[u8]) -> Vec<u8> {
+        let sps = (self.rate / SYMBOL_RATE) as usize;
+        let pulse = filter::root_raised_cosine(CQPSK_SPAN * sps + 1, sps as f32,
+                                               CQPSK_ROLLOFF);
+        let half = CQPSK_SPAN * sps / 2;
+
+        // The pulse is normalized to unity gain, so its peak is about 1/sps.
+        let scale = CQPSK_AMPLITUDE * sps as f32;
+
+        // Differentially encode the symbols into constellation points.
+        let mut phase = 0.0;
+
+        let points: Vec<(f32, f32)> = dibits.iter().map(|&d| {
+            phase = (phase + symbol(d) as f64 * PI / 4.0) % (2.0 * PI);
+            (phase.cos() as f32, phase.sin() as f32)
+        }).collect();
+
+        let step = 2.0 * PI * self.offset as f64 / self.rate as f64;
+        let mut bytes = Vec::with_capacity(dibits.len() * sps * 2);
+
+        for n in 0..dibits.len() * sps {
+            let first = (n / sps).saturating_sub(CQPSK_SPAN / 2 + 1);
+            let last = std::cmp::min(n / sps + CQPSK_SPAN / 2 + 2, points.len());
+
+            // Sum the pulses of nearby symbols, each centered in its symbol period.
+            let (mut i, mut q) = (0.0, 0.0);
+
+            for k in first..last {
+                let center = k * sps + sps / 2;
+
+                if n + half < center || n > center + half {
+                    continue;
+                }
+
+                let tap = pulse[n + half - center] * scale;
+
+                i += points[k].0 * tap;
+                q += points[k].1 * tap;
+            }
+
+            // Shift onto the carrier offset.
+            self.phase = (self.phase + step) % (2.0 * PI);
+            let (sin, cos) = (self.phase.sin() as f32, self.phase.cos() as f32);
+
+            let (i, q) = (i * cos - q * sin, i * sin + q * cos);
+
+            bytes.push(quantize(i + self.gaussian()));
+            bytes.push(quantize(q + self.gaussian()));
+        }
+
+        bytes
+    }
+
+    /// Generate a sample of gaussian noise.
+    fn gaussian(&mut self) -> f32 {
+        if self.noise == 0.0 {
+            return 0.0;
+        }
+
+        // Box-Muller transform.
+        let u: f32 = 1.0 - self.rng.gen::<f32>();
+        let v: f32 = self.rng.gen();
+
+        0.5 * self.noise * (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+    }
+}
+
+/// Convert the given dibits into the ideal baseband a demodulator produces at the given
+/// rate (samples/sec), scaled so `DEVIATION` is unity.
+pub fn baseband(rate: u32, dibits: &[u8]) -> Vec<f32> {
+    assert!(rate % SYMBOL_RATE == 0);
+
+    let sps = (rate / SYMBOL_RATE) as usize;
+
+    dibits.iter().flat_map(|&d| {
+        let level = symbol(d) * OUTER_DEVIATION / 3.0 / DEVIATION as f32;
+        std::iter::repeat(level).take(sps)
+    }).collect()
+}
+
+/// Convert the given dibit to its C4FM symbol level.
+fn symbol(dibit: u8) -> f32 {
+    match dibit {
+        0b01 => 3.0,
+        0b00 => 1.0,
+        0b10 => -1.0,
+        0b11 => -3.0,
+        _ => panic!("invalid dibit"),
+    }
+}
+
+/// Convert the given I/Q component in [-1, 1] to an unsigned byte centered on 127.5.
+fn quantize(x: f32) -> u8 {
+    (127.5 + 127.5 * x).round().max(0.0).min(255.0) as u8
+}
+
+/// Split the given number of low bits of the given word into dibits, most significant
+/// first.
+fn dibits(word: u64, bits: usize) -> Vec<u8> {
+    (0..bits / 2).rev().map(|n| (word >> (n * 2)) as u8 & 0b11).collect()
+}
+
+/// Encode the given NAC and data unit ID into a 64-bit NID.
+pub fn nid(nac: u16, duid: u8) -> u64 {
+    let data = (nac as u64 & 0xFFF) << 4 | duid as u64 & 0xF;
+
+    // Systematic BCH codeword with the data in the upper bits.
+    let mut rem = data << 47;
+
+    for bit in (47..63).rev() {
+        if rem >> bit & 1 == 1 {
+            rem ^= BCH_GEN << (bit - 47);
+        }
+    }
+
+    let word = data << 47 | rem;
+
+    // Final bit gives the codeword even parity.
+    word << 1 | (word.count_ones() as u64 & 1)
+}
+
+/// Build a data unit with the given NAC, data unit ID, and body dibits, adding frame
+/// sync, the NID, and status symbols.
+pub fn data_unit(nac: u16, duid: u8, body: &[u8]) -> Vec<u8> {
+    let mut stream = dibits(FRAME_SYNC, 48);
+    stream.extend(dibits(nid(nac, duid), 64));
+    stream.extend_from_slice(body);
+
+    let mut out = Vec::with_capacity(stream.len() + stream.len() / STATUS_PERIOD);
+
+    for (n, &d) in stream.iter().enumerate() {
+        if n > 0 && n % STATUS_PERIOD == 0 {
+            out.push(STATUS);
+        }
+
+        out.push(d);
+    }
+
+    out
+}
+
+/// Build a single-block TSBK with the given opcode, manufacturer ID, and payload,
+/// including its CRC.
+pub fn tsbk(opcode: u8, mfg: u8, payload: [u8; 8]) -> [u8; 12] {
+    let mut bytes = [0; 12];
+
+    // Last block flag.
+    bytes[0] = 0x80 | opcode & 0x3F;
+    bytes[1] = mfg;
+    bytes[2..10].copy_from_slice(&payload[..]);
+
+    let crc = crc_ccitt(&bytes[..10]);
+    bytes[10] = (crc >> 8) as u8;
+    bytes[11] = crc as u8;
+
+    bytes
+}
+
+/// Build a trunking signaling data unit with the given NAC carrying the given TSBK.
+pub fn tsbk_unit(nac: u16, tsbk: [u8; 12]) -> Vec<u8> {
+    data_unit(nac, DUID_TSBK, &trellis(&tsbk)[..])
+}
+
+/// Build an LDU1 with the given NAC and coded body dibits.
+pub fn ldu1_unit(nac: u16, body: &[u8]) -> Vec<u8> {
+    assert!(body.len() == LDU_DIBITS);
+    data_unit(nac, DUID_LDU1, body)
+}
+
+/// Build an LDU2 with the given NAC and coded body dibits.
+pub fn ldu2_unit(nac: u16, body: &[u8]) -> Vec<u8> {
+    assert!(body.len() == LDU_DIBITS);
+    data_unit(nac, DUID_LDU2, body)
+}
+
+/// Build the coded body of an LDU1 carrying the given link control word.
+///
+/// Voice frames and low speed data are left null.
+pub fn ldu1_body(lc: [u8; 9]) -> Vec<u8> {
+    // (24, 12, 13) Reed-Solomon code.
+    ldu_body(&reed_solomon(&hexbits(&lc[..])[..], 12)[..])
+}
+
+/// Build the coded body of an LDU2 carrying the given encryption sync word.
+///
+/// Voice frames and low speed data are left null.
+pub fn ldu2_body(es: [u8; 12]) -> Vec<u8> {
+    // (24, 16, 9) Reed-Solomon code.
+    ldu_body(&reed_solomon(&hexbits(&es[..])[..], 8)[..])
+}
+
+/// Build the coded body of an LDU with the given 24 Reed-Solomon coded hexbits spread
+/// over the blocks between voice frames.
+fn ldu_body(coded: &[u8]) -> Vec<u8> {
+    assert!(coded.len() == 24);
+
+    let blocks: Vec<Vec<u8>> = coded.chunks(4).map(|words| {
+        words.iter().flat_map(|&h| dibits(hamming(h) as u64, 10)).collect()
+    }).collect();
+
+    let voice = [0; VOICE_DIBITS];
+    let mut body = Vec::with_capacity(LDU_DIBITS);
+
+    // Two voice frames lead, then a block follows each of the next six, and low speed
+    // data sits before the last.
+    body.extend_from_slice(&voice[..]);
+
+    for block in blocks.iter() {
+        body.extend_from_slice(&voice[..]);
+        body.extend_from_slice(&block[..]);
+    }
+
+    body.extend_from_slice(&voice[..]);
+    body.extend_from_slice(&[0; LSD_DIBITS][..]);
+    body.extend_from_slice(&voice[..]);
+
+    body
+}
+
+/// Split the given bytes into hexbits, most significant first.
+fn hexbits(bytes: &[u8]) -> Vec<u8> {
+    assert!(bytes.len() * 8 % 6 == 0);
+
+    (0..bytes.len() * 8 / 6).map(|n| {
+        (0..6).fold(0, |h, b| {
+            let bit = n * 6 + b;
+            h << 1 | bytes[bit / 8] >> (7 - bit % 8) & 1
+        })
+    }).collect()
+}
+
+/// Encode the given hexbit with the shortened (10, 6, 3) Hamming code.
+fn hamming(data: u8) -> u16 {
+    let parity = HAMMING_GEN.iter().fold(0, |p, &row| {
+        p << 1 | (data & row).count_ones() as u16 & 1
+    });
+
+    (data as u16) << 4 | parity
+}
+
+/// Multiply the given elements of GF(2^6).
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut prod = 0;
+
+    while b != 0 {
+        if b & 1 == 1 {
+            prod ^= a;
+        }
+
+        a <<= 1;
+
+        if a & 0x40 != 0 {
+            a ^= GF_POLY;
+        }
+
+        b >>= 1;
+    }
+
+    prod
+}
+
+/// Raise the primitive element of GF(2^6) to the given power.
+fn gf_pow(pow: usize) -> u8 {
+    (0..pow).fold(1, |x, _| gf_mul(x, 2))
+}
+
+/// Encode the given hexbits with a systematic Reed-Solomon code adding the given number
+/// of parity hexbits, with the first hexbit as the highest order coefficient and the
+/// generator having roots at the first powers of the primitive element.
+fn reed_solomon(data: &[u8], parity: usize) -> Vec<u8> {
+    // Generator coefficients, lowest order first.
+    let mut gen = vec![1];
+
+    for pow in 1..=parity {
+        let root = gf_pow(pow);
+        let mut next = vec![0; gen.len() + 1];
+
+        for (n, &g) in gen.iter().enumerate() {
+            next[n + 1] ^= g;
+            next[n] ^= gf_mul(g, root);
+        }
+
+        gen = next;
+    }
+
+    // Remainder of the data shifted by the generator degree, highest order first.
+    let mut rem = vec![0; parity];
+
+    for &d in data.iter() {
+        let feedback = d ^ rem[0];
+
+        rem.remove(0);
+        rem.push(0);
+
+        for (n, r) in rem.iter_mut().enumerate() {
+            *r ^= gf_mul(feedback, gen[parity - 1 - n]);
+        }
+    }
+
+    let mut word = data.to_vec();
+    word.extend(rem);
+    word
+}
+
+/// Build a simple terminator with the given NAC.
+pub fn tdu_unit(nac: u16) -> Vec<u8> {
+    data_unit(nac, DUID_TDU, &[0; TDU_DIBITS][..])
+}
+
+/// Calculate the inverted CRC-CCITT of the given bytes.
+fn crc_ccitt(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for &b in bytes.iter() {
+        for bit in (0..8).rev() {
+            let feedback = (crc >> 15) as u8 ^ (b >> bit & 1);
+            crc <<= 1;
+
+            if feedback == 1 {
+                crc ^= 0x1021;
+            }
+        }
+    }
+
+    !crc
+}
+
+/// Encode the given bytes with the half-rate trellis code and interleave the resulting
+/// constellation points.
+fn trellis(bytes: &[u8; 12]) -> Vec<u8> {
+    let mut state = 0;
+
+    let mut input: Vec<u8> = bytes.iter()
+        .flat_map(|&b| dibits(b as u64, 8))
+        .collect();
+
+    // Flush dibit returns the encoder to its initial state.
+    input.push(0);
+
+    let points: Vec<usize> = input.iter().map(|&d| {
+        let point = TRELLIS_STATES[state][d as usize];
+        state = d as usize;
+        point
+    }).collect();
+
+    // Transmit every fourth point, starting from each of the first four in turn.
+    (0..4).flat_map(|start| points.iter().skip(start).step_by(4))
+        .flat_map(|&p| {
+            let (a, b) = CONSTELLATION[p];
+            vec![a, b]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nid() {
+        // The code is linear, so an all-zero NID encodes to all zeros.
+        assert_eq!(nid(0, 0), 0);
+
+        // Every codeword is a multiple of the generator.
+        for &(nac, duid) in [(0x293, DUID_TSBK), (0xFFF, 0xF), (0x001, DUID_LDU1)].iter() {
+            let mut rem = nid(nac, duid) >> 1;
+
+            for bit in (47..63).rev() {
+                if rem >> bit & 1 == 1 {
+                    rem ^= BCH_GEN << (bit - 47);
+                }
+            }
+
+            assert_eq!(rem, 0);
+            assert_eq!(nid(nac, duid) >> 48, (nac as u64) << 4 | duid as u64);
+            assert_eq!(nid(nac, duid).count_ones() % 2, 0);
+        }
+    }
+
+    #[test]
+    fn test_hexbits() {
+        assert_eq!(hexbits(&[0b00000100, 0b00100000, 0b11111111][..]),
+                   vec![0b000001, 0b000010, 0b000011, 0b111111]);
+    }
+
+    #[test]
+    fn test_hamming() {
+        assert_eq!(hamming(0), 0);
+        assert_eq!(hamming(0b100000), 0b100000_1110);
+
+        // Every pair of codewords differs in at least three bits.
+        for a in 0..64 {
+            for b in a + 1..64 {
+                assert!((hamming(a) ^ hamming(b)).count_ones() >= 3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reed_solomon() {
+        assert_eq!(gf_pow(6), 0b000011);
+        assert_eq!(gf_pow(63), 1);
+        assert_eq!(gf_mul(gf_pow(20), gf_pow(50)), gf_pow(7));
+
+        let data: Vec<u8> = (1..13).collect();
+        let word = reed_solomon(&data[..], 12);
+
+        assert_eq!(word.len(), 24);
+        assert_eq!(&word[..12], &data[..]);
+
+        // The codeword vanishes at each root of the generator.
+        for pow in 1..13 {
+            let root = gf_pow(pow);
+            assert_eq!(word.iter().fold(0, |s, &c| gf_mul(s, root) ^ c), 0);
+        }
+    }
+
+    #[test]
+    fn test_ldu_body() {
+        let body = ldu1_body([0; 9]);
+        assert_eq!(body.len(), LDU_DIBITS);
+        assert!(body.iter().all(|&d| d == 0));
+
+        let body = ldu2_body([0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0]);
+        assert_eq!(body.len(), LDU_DIBITS);
+
+        // The algorithm ID leads the fourth block, after the fifth voice frame.
+        let start = 5 * VOICE_DIBITS + 3 * 20;
+        let words: Vec<u16> = body[start..start + 20].chunks(5)
+            .map(|w| w.iter().fold(0, |x, &d| x << 2 | d as u16))
+            .collect();
+
+        assert_eq!(words, vec![hamming(0b100000), 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_crc() {
+        // Inverted CRC-16/XMODEM check value.
+        assert_eq!(crc_ccitt(b"123456789"), !0x31C3);
+    }
+
+    #[test]
+    fn test_data_unit() {
+        let du = data_unit(0x293, DUID_TSBK, &[0b11; 100][..]);
+
+        // 24 sync dibits, 32 NID dibits, and the body, with a status symbol after every
+        // 35 dibits.
+        assert_eq!(du.len(), 156 + 4);
+        assert_eq!(&du[..4], &[0b01, 0b01, 0b01, 0b01]);
+        assert_eq!(du[35], STATUS);
+        assert_eq!(du[71], STATUS);
+        assert_eq!(du[36], dibits(nid(0x293, DUID_TSBK), 64)[11]);
+    }
+
+    #[test]
+    fn test_trellis() {
+        let coded = trellis(&[0; 12]);
+        assert_eq!(coded.len(), 98);
+
+        // Zeros stay in the initial state, producing the first constellation point.
+        for pair in coded.chunks(2) {
+            assert_eq!((pair[0], pair[1]), CONSTELLATION[0]);
+        }
+
+        let coded = trellis(&[0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // The first two inputs are 01 and 00, which are transmitted first and second in
+        // the first and second interleave rows.
+        assert_eq!((coded[0], coded[1]), CONSTELLATION[15]);
+        assert_eq!((coded[26], coded[27]), CONSTELLATION[4]);
+    }
+
+    #[test]
+    fn test_baseband() {
+        let bb = baseband(48000, &[0b01, 0b00, 0b10, 0b11][..]);
+
+        assert_eq!(bb.len(), 40);
+        assert!((bb[0] - 0.36).abs() < 1e-6);
+        assert!((bb[9] - 0.36).abs() < 1e-6);
+        assert!((bb[10] - 0.12).abs() < 1e-6);
+        assert!((bb[25] + 0.12).abs() < 1e-6);
+        assert!((bb[39] + 0.36).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_modulate() {
+        let mut m = Modulator::new(48000, 1800.0, 0.0, 0.0);
+        let bytes = m.modulate(&[0b01, 0b11][..]);
+
+        assert_eq!(bytes.len(), 2 * 10 * 2);
+
+        // Phase advances by 1800Hz per sample for the first symbol.
+        let first = (bytes[0] as f32 - 127.5) / 127.5;
+        let step = 2.0 * std::f32::consts::PI * 1800.0 / 48000.0;
+        assert!((first - 0.5 * step.cos()).abs() < 0.01);
+
+        let last = bytes.len() - 2;
+        let (i, q) = ((bytes[last] as f32 - 127.5) / 127.5,
+                      (bytes[last + 1] as f32 - 127.5) / 127.5);
+
+        // Equal time at ±1800Hz returns to zero phase.
+        assert!((i - 0.5).abs() < 0.01);
+        assert!(q.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_modulate_cqpsk() {
+        let mut m = Modulator::new(48000, 1800.0, 0.0, 0.0);
+        let bytes = m.modulate_cqpsk(&[0b00; 32][..]);
+
+        assert_eq!(bytes.len(), 32 * 10 * 2);
+
+        let phase = |n: usize| {
+            let (i, q) = ((bytes[n * 2] as f32 - 127.5) / 127.5,
+                          (bytes[n * 2 + 1] as f32 - 127.5) / 127.5);
+            q.atan2(i)
+        };
+
+        // Each +1 symbol rotates the phase by π/4, measured between symbol centers
+        // away from the edges.
+        let rot = phase(16 * 10 + 5) - phase(15 * 10 + 5);
+        let rot = (rot + 3.0 * std::f32::consts::PI) % (2.0 * std::f32::consts::PI) -
+            std::f32::consts::PI;
+
+        assert!((rot - std::f32::consts::PI / 4.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,230 @@
+//! Motorola manufacturer-specific trunking signaling packets.
+//!
+//! These carry talkgroup patches (supergroups), the channel grants for patched calls,
+//! and the site's base station identifier, none of which the standard packets cover.
+
+use p25::trunking::tsbk::TsbkFields;
+
+/// Manufacturer ID of Motorola packets.
+pub const MFG_MOTOROLA: u8 = 0x90;
+
+/// Opcodes of Motorola packets.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MotorolaOpcode {
+    /// Talkgroups were added to a patch.
+    PatchAdd,
+    /// Talkgroups were removed from a patch.
+    PatchDelete,
+    /// Channel granted to a patch.
+    PatchGrant,
+    /// Channels of patches in progress.
+    PatchGrantUpdate,
+    /// Control channel base station identifier.
+    BaseStationId,
+}
+
+impl MotorolaOpcode {
+    /// Parse the given 6-bit opcode, if it's known.
+    pub fn from_bits(bits: u8) -> Option<MotorolaOpcode> {
+        use self::MotorolaOpcode::*;
+
+        match bits & 0x3F {
+            0x00 => Some(PatchAdd),
+            0x01 => Some(PatchDelete),
+            0x02 => Some(PatchGrant),
+            0x03 => Some(PatchGrantUpdate),
+            0x0B => Some(BaseStationId),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the opcode of the given Motorola packet, if it's known.
+///
+/// The standard opcode mapping can't represent the reserved values Motorola uses, so
+/// the raw opcode bits are parsed instead.
+pub fn opcode(tsbk: &TsbkFields) -> Option<MotorolaOpcode> {
+    MotorolaOpcode::from_bits(tsbk.raw_opcode())
+}
+
+/// Channel identifier and number in some channel plan.
+pub type ChannelRef = (u8, u16);
+
+/// Parse the 16-bit channel at the start of the given bytes.
+fn channel(bytes: &[u8]) -> ChannelRef {
+    (bytes[0] >> 4, (bytes[0] as u16 & 0xF) << 8 | bytes[1] as u16)
+}
+
+/// Parse the 16-bit big-endian word at the start of the given bytes.
+fn word(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+/// Supergroup and member talkgroups of a patch add or delete packet.
+pub struct PatchGroups<'a>(&'a [u8]);
+
+impl<'a> PatchGroups<'a> {
+    /// Interpret the given payload as a patch add or delete packet.
+    pub fn new(payload: &'a [u8]) -> Self {
+        PatchGroups(payload)
+    }
+
+    /// Talkgroup the patched talkgroups are carried on.
+    pub fn supergroup(&self) -> u16 {
+        word(&self.0[0..])
+    }
+
+    /// Talkgroups added to or removed from the patch.
+    ///
+    /// Unused slots repeat the supergroup or hold zero, and are skipped.
+    pub fn groups(&self) -> Vec<u16> {
+        let sg = self.supergroup();
+        let mut groups = vec![];
+
+        for tg in self.0[2..8].chunks(2).map(word) {
+            if tg != 0 && tg != sg && !groups.contains(&tg) {
+                groups.push(tg);
+            }
+        }
+
+        groups
+    }
+}
+
+/// Channel grant for a patch.
+pub struct PatchGrant<'a>(&'a [u8]);
+
+impl<'a> PatchGrant<'a> {
+    /// Interpret the given payload as a patch grant packet.
+    pub fn new(payload: &'a [u8]) -> Self {
+        PatchGrant(payload)
+    }
+
+    /// Traffic channel the patch is carried on.
+    pub fn channel(&self) -> ChannelRef {
+        channel(&self.0[1..])
+    }
+
+    /// Supergroup of the patch.
+    pub fn supergroup(&self) -> u16 {
+        word(&self.0[3..])
+    }
+
+    /// Unit ID of the radio that started the call.
+    pub fn src_unit(&self) -> u32 {
+        (self.0[5] as u32) << 16 | (self.0[6] as u32) << 8 | self.0[7] as u32
+    }
+}
+
+/// Channels of two patches in progress.
+pub struct PatchGrantUpdate<'a>(&'a [u8]);
+
+impl<'a> PatchGrantUpdate<'a> {
+    /// Interpret the given payload as a patch grant update packet.
+    pub fn new(payload: &'a [u8]) -> Self {
+        PatchGrantUpdate(payload)
+    }
+
+    /// Traffic channel and supergroup of each patch.
+    pub fn updates(&self) -> [(ChannelRef, u16); 2] {
+        [
+            (channel(&self.0[0..]), word(&self.0[2..])),
+            (channel(&self.0[4..]), word(&self.0[6..])),
+        ]
+    }
+}
+
+/// Base station identifier of the control channel.
+pub struct BaseStationId<'a>(&'a [u8]);
+
+impl<'a> BaseStationId<'a> {
+    /// Interpret the given payload as a base station identifier packet.
+    pub fn new(payload: &'a [u8]) -> Self {
+        BaseStationId(payload)
+    }
+
+    /// Callsign the site identifies with, packed as 6-bit characters.
+    pub fn callsign(&self) -> String {
+        let bits = self.0[..6].iter().fold(0u64, |w, &b| w << 8 | b as u64);
+
+        (0..8).rev().map(|n| char::from(((bits >> (n * 6)) as u8 & 0x3F) + 43)).collect()
+    }
+
+    /// Control channel the identifier belongs to.
+    pub fn channel(&self) -> ChannelRef {
+        channel(&self.0[6..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use modulator;
+
+    #[test]
+    fn test_raw_opcode() {
+        let payload = [0xFF, 0xF0, 0x00, 0x64, 0x00, 0xC8, 0xFF, 0xF0];
+
+        for &(bits, op) in [(0x00, Some(MotorolaOpcode::PatchAdd)),
+                            (0x01, Some(MotorolaOpcode::PatchDelete)),
+                            (0x0B, Some(MotorolaOpcode::BaseStationId)),
+                            (0x2A, None)].iter()
+        {
+            let tsbk = TsbkFields::new(modulator::tsbk(bits, MFG_MOTOROLA, payload));
+            assert_eq!(opcode(&tsbk), op);
+        }
+    }
+
+    #[test]
+    fn test_opcodes() {
+        assert_eq!(MotorolaOpcode::from_bits(0x00), Some(MotorolaOpcode::PatchAdd));
+        assert_eq!(MotorolaOpcode::from_bits(0x80 | 0x01),
+                   Some(MotorolaOpcode::PatchDelete));
+        assert_eq!(MotorolaOpcode::from_bits(0x0B), Some(MotorolaOpcode::BaseStationId));
+        assert_eq!(MotorolaOpcode::from_bits(0x3F), None);
+    }
+
+    #[test]
+    fn test_patch_groups() {
+        let p = PatchGroups::new(&[0xFF, 0xF0, 0x00, 0x64, 0x00, 0xC8, 0xFF, 0xF0]);
+        assert_eq!(p.supergroup(), 0xFFF0);
+        assert_eq!(p.groups(), vec![100, 200]);
+
+        let p = PatchGroups::new(&[0xFF, 0xF0, 0x00, 0x64, 0x00, 0x64, 0x00, 0x00]);
+        assert_eq!(p.groups(), vec![100]);
+    }
+
+    #[test]
+    fn test_patch_grant() {
+        let g = PatchGrant::new(&[0x00, 0x11, 0x23, 0xFF, 0xF0, 0x00, 0xAB, 0xCD]);
+        assert_eq!(g.channel(), (1, 0x123));
+        assert_eq!(g.supergroup(), 0xFFF0);
+        assert_eq!(g.src_unit(), 0xABCD);
+
+        let u = PatchGrantUpdate::new(&[0x11, 0x23, 0xFF, 0xF0, 0x20, 0x05, 0xFF, 0xF1]);
+        assert_eq!(u.updates(), [((1, 0x123), 0xFFF0), ((2, 0x005), 0xFFF1)]);
+    }
+
+    #[test]
+    fn test_base_station_id() {
+        let mut bits = 0u64;
+
+        // Each character is offset from '+' in 6 bits.
+        for &c in b"WQAB123A".iter() {
+            bits = bits << 6 | (c - 43) as u64;
+        }
+
+        let mut payload = [0; 8];
+
+        for n in 0..6 {
+            payload[n] = (bits >> (40 - n * 8)) as u8;
+        }
+
+        payload[6] = 0x10;
+        payload[7] = 0x42;
+
+        let b = BaseStationId::new(&payload[..]);
+        assert_eq!(b.channel(), (1, 0x042));
+        assert_eq!(b.callsign(), "WQAB123A");
+    }
+}
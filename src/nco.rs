@@ -0,0 +1,85 @@
+//! Numerically-controlled oscillator for digital frequency shifting.
+
+use std::f64::consts::PI;
+
+use num::complex::Complex32;
+
+/// Generates a complex sinusoid used to shift signals in frequency.
+pub struct Nco {
+    /// Current phase (radians).
+    phase: f64,
+    /// Phase increment per sample (radians).
+    step: f64,
+    /// Sample rate (samples/sec).
+    rate: f64,
+}
+
+impl Nco {
+    /// Create a new `Nco` oscillating at the given frequency (Hz) with the given sample
+    /// rate (samples/sec).
+    pub fn new(freq: f32, rate: u32) -> Self {
+        let mut nco = Nco {
+            phase: 0.0,
+            step: 0.0,
+            rate: rate as f64,
+        };
+
+        nco.set_freq(freq);
+        nco
+    }
+
+    /// Change the oscillator frequency (Hz), keeping the phase continuous.
+    pub fn set_freq(&mut self, freq: f32) {
+        self.step = 2.0 * PI * freq as f64 / self.rate;
+    }
+
+    /// Retrieve the current oscillator frequency (Hz).
+    pub fn freq(&self) -> f32 {
+        (self.step * self.rate / (2.0 * PI)) as f32
+    }
+
+    /// Shift the given sample up by the oscillator frequency.
+    pub fn mix(&mut self, s: Complex32) -> Complex32 {
+        let (sin, cos) = self.phase.sin_cos();
+
+        self.phase += self.step;
+
+        // Keep the phase bounded to preserve precision.
+        if self.phase > PI {
+            self.phase -= 2.0 * PI;
+        } else if self.phase < -PI {
+            self.phase += 2.0 * PI;
+        }
+
+        s * Complex32::new(cos as f32, sin as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nco() {
+        let mut nco = Nco::new(1000.0, 8000);
+        assert!((nco.freq() - 1000.0).abs() < 1e-3);
+
+        let one = Complex32::new(1.0, 0.0);
+        let expected = [(1.0, 0.0), (0.7071, 0.7071), (0.0, 1.0), (-0.7071, 0.7071),
+                        (-1.0, 0.0), (-0.7071, -0.7071), (0.0, -1.0), (0.7071, -0.7071),
+                        (1.0, 0.0)];
+
+        for &(re, im) in expected.iter() {
+            let s = nco.mix(one);
+            assert!((s.re - re).abs() < 1e-3);
+            assert!((s.im - im).abs() < 1e-3);
+        }
+
+        // Phase continues from the last sample after a frequency change.
+        nco.set_freq(-2000.0);
+        nco.mix(one);
+        let s = nco.mix(one);
+        assert!((s.re - 0.7071).abs() < 1e-3);
+        assert!((s.im + 0.7071).abs() < 1e-3);
+    }
+}
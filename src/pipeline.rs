@@ -0,0 +1,177 @@
+//! Health counters for the chunks passed between tasks.
+//!
+//! Buffers between tasks come from fixed pools, so a task that falls behind eventually
+//! starves the one feeding it. Rather than aborting, the starved task drops its output
+//! and counts the drop here, and each queue between tasks tracks its depth so a backlog
+//! is visible before it turns into drops.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Largest number of voice frames queued for audio output before more are dropped,
+/// about a second of audio.
+pub const AUDIO_BACKLOG: usize = 50;
+
+/// Largest number of I/Q chunks queued for each demodulator before a live SDR drops
+/// more or a recording waits.
+pub const IQ_BACKLOG: usize = 14;
+
+/// Number of items queued between two tasks.
+#[derive(Default)]
+pub struct Backlog {
+    /// Items currently queued.
+    cur: AtomicUsize,
+    /// Most items queued at once since the last reset.
+    max: AtomicUsize,
+}
+
+impl Backlog {
+    /// Record that an item is about to be queued.
+    ///
+    /// This must happen before the item is sent, so the receiver can't record taking it
+    /// first.
+    pub fn push(&self) {
+        let cur = self.cur.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max.fetch_max(cur, Ordering::Relaxed);
+    }
+
+    /// Record that an item was taken from the queue.
+    pub fn pop(&self) {
+        self.cur.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Retrieve the number of items currently queued.
+    pub fn depth(&self) -> usize {
+        self.cur.load(Ordering::Relaxed)
+    }
+
+    /// Take a copy of the counters.
+    fn stats(&self) -> BacklogStats {
+        BacklogStats {
+            cur: self.depth(),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Restart the maximum from the current depth.
+    fn reset(&self) {
+        self.max.store(self.depth(), Ordering::Relaxed);
+    }
+}
+
+/// Counters shared by every task along the pipeline.
+#[derive(Default)]
+pub struct Pipeline {
+    /// SDR chunks dropped because the demodulators fell behind.
+    sdr_drops: AtomicUsize,
+    /// Baseband chunks dropped because the receiver fell behind.
+    baseband_drops: AtomicUsize,
+    /// Voice frames dropped because audio output fell behind.
+    audio_drops: AtomicUsize,
+    /// SDR chunks queued for demodulation.
+    pub iq: Backlog,
+    /// Baseband chunks queued for the receiver.
+    pub baseband: Backlog,
+    /// Events queued for audio output.
+    pub audio: Backlog,
+}
+
+impl Pipeline {
+    /// Record that an SDR chunk was dropped.
+    pub fn record_sdr_drop(&self) {
+        self.sdr_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a baseband chunk was dropped.
+    pub fn record_baseband_drop(&self) {
+        self.baseband_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a voice frame was dropped.
+    pub fn record_audio_drop(&self) {
+        self.audio_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take a copy of the counters.
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            sdr_drops: self.sdr_drops.load(Ordering::Relaxed),
+            baseband_drops: self.baseband_drops.load(Ordering::Relaxed),
+            audio_drops: self.audio_drops.load(Ordering::Relaxed),
+            iq: self.iq.stats(),
+            baseband: self.baseband.stats(),
+            audio: self.audio.stats(),
+        }
+    }
+
+    /// Clear the drop counters and restart the backlog maximums.
+    pub fn reset(&self) {
+        self.sdr_drops.store(0, Ordering::Relaxed);
+        self.baseband_drops.store(0, Ordering::Relaxed);
+        self.audio_drops.store(0, Ordering::Relaxed);
+        self.iq.reset();
+        self.baseband.reset();
+        self.audio.reset();
+    }
+}
+
+/// Copy of the counters of a `Backlog`.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+pub struct BacklogStats {
+    /// Items currently queued.
+    pub cur: usize,
+    /// Most items queued at once since the last reset.
+    pub max: usize,
+}
+
+/// Copy of the counters of a `Pipeline`.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+pub struct PipelineStats {
+    /// SDR chunks dropped because the demodulators fell behind.
+    pub sdr_drops: usize,
+    /// Baseband chunks dropped because the receiver fell behind.
+    pub baseband_drops: usize,
+    /// Voice frames dropped because audio output fell behind.
+    pub audio_drops: usize,
+    /// SDR chunks queued for demodulation.
+    pub iq: BacklogStats,
+    /// Baseband chunks queued for the receiver.
+    pub baseband: BacklogStats,
+    /// Events queued for audio output.
+    pub audio: BacklogStats,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let p = Pipeline::default();
+
+        p.iq.push();
+        p.iq.push();
+        p.iq.pop();
+        p.iq.push();
+        p.iq.pop();
+
+        p.record_sdr_drop();
+        p.record_audio_drop();
+        p.record_audio_drop();
+
+        let s = p.stats();
+
+        assert_eq!(s.iq, BacklogStats { cur: 1, max: 2 });
+        assert_eq!(s.baseband, BacklogStats::default());
+        assert_eq!(s.sdr_drops, 1);
+        assert_eq!(s.baseband_drops, 0);
+        assert_eq!(s.audio_drops, 2);
+
+        p.reset();
+
+        let s = p.stats();
+
+        assert_eq!(s.iq, BacklogStats { cur: 1, max: 1 });
+        assert_eq!(s.sdr_drops, 0);
+        assert_eq!(s.audio_drops, 0);
+    }
+}
@@ -3,6 +3,8 @@
 use p25::message::nid::NetworkId;
 use p25::message::nid::DataUnit::*;
 
+use quality::SignalQuality;
+
 use self::PolicyEvent::*;
 use self::ReceiverState::*;
 use self::StateChange::*;
@@ -40,6 +42,7 @@ enum StateChange {
 }
 
 /// Policy state machine for P25 receiver.
+#[derive(Clone)]
 pub struct ReceiverPolicy {
     /// Current state.
     state: ReceiverState,
@@ -49,21 +52,26 @@ pub struct ReceiverPolicy {
     watchdog_time: usize,
     /// Call term pause timeout.
     pause_time: usize,
+    /// Lowest SNR (dB) tolerated on a traffic channel.
+    min_snr: Option<f32>,
 }
 
 impl ReceiverPolicy {
     /// Create a new `ReceiverPolicy` with the given talkgroup selection timeout, watchdog
-    /// timeout, and call termination pause timeout.
+    /// timeout, call termination pause timeout, and lowest traffic channel SNR (dB).
     ///
     /// Each timeout should be given as an amount of baseband samples.
     ///
     /// The policy is initialized to start on the control channel.
-    pub fn new(select: usize, watchdog: usize, pause: usize) -> Self {
+    pub fn new(select: usize, watchdog: usize, pause: usize, min_snr: Option<f32>)
+        -> Self
+    {
         ReceiverPolicy {
             state: Control(Timer::new(select)),
             select_time: select,
             watchdog_time: watchdog,
             pause_time: pause,
+            min_snr: min_snr,
         }
     }
 
@@ -104,10 +112,11 @@ impl ReceiverPolicy {
                     Change(self.state_traffic(false))
                 },
                 // Ignore spurious TSBKs that occur immediately after switching to a
-                // traffic channel. The NID for these gets decoded from the control
-                // channel backlog samples buffered by librtlsdr, but an unrecoverable
-                // Viterbi error typically follows due to the frequency switch causing a
-                // change in stream in the middle of the packet.
+                // traffic channel. Samples are tagged with the tuning they were captured
+                // under, but sources like rtl_tcp can't mark exactly where a retune takes
+                // effect, so some control channel backlog may still arrive. An
+                // unrecoverable Viterbi error typically follows due to the frequency
+                // switch causing a change in stream in the middle of the packet.
                 TrunkingSignaling => Event(Resync),
                 _ => NoChange,
             },
@@ -135,6 +144,21 @@ impl ReceiverPolicy {
         self.handle_change(next)
     }
 
+    /// Record a chunk of baseband received without a carrier.
+    pub fn handle_carrier_lost(&mut self) -> Option<PolicyEvent> {
+        self.handle_change(match self.state {
+            // Hunting handles a bad control channel.
+            Control(..) => NoChange,
+            // The transmission has ended, so wait for it to be continued like after a
+            // terminator.
+            Traffic(..) => {
+                debug!("carrier lost, pausing for voice message continuation");
+                Change(Paused(Timer::new(self.pause_time)))
+            },
+            Paused(..) => NoChange,
+        })
+    }
+
     /// Record a received `CallTermination` indicator.
     pub fn handle_call_term(&mut self) -> Option<PolicyEvent> {
         self.handle_change(match self.state {
@@ -150,6 +174,27 @@ impl ReceiverPolicy {
         })
     }
 
+    /// Record a measurement of the current channel's signal quality.
+    pub fn handle_quality(&mut self, q: &SignalQuality) -> Option<PolicyEvent> {
+        let min = match self.min_snr {
+            Some(min) => min,
+            None => return None,
+        };
+
+        self.handle_change(match self.state {
+            // Hunting handles a bad control channel.
+            Control(..) => NoChange,
+            // Give up on a traffic channel too weak to decode rather than waiting out
+            // the watchdog.
+            Traffic(..) | Paused(..) => if q.level.snr < min {
+                debug!("traffic channel SNR {} dB is too low", q.level.snr);
+                Event(ReturnControl)
+            } else {
+                NoChange
+            },
+        })
+    }
+
     /// Indicate the receiver has moved to a traffic channel.
     pub fn enter_traffic(&mut self) {
         self.state = self.state_traffic(true);
@@ -221,7 +266,7 @@ mod test {
 
     #[test]
     fn test_policy() {
-        let mut p = ReceiverPolicy::new(10, 20, 30);
+        let mut p = ReceiverPolicy::new(10, 20, 30, None);
         assert_eq!(p.handle_elapsed(9), None);
         assert_eq!(p.handle_elapsed(1), Some(ChooseTalkgroup));
         assert_eq!(p.handle_elapsed(1), None);
@@ -315,4 +360,52 @@ mod test {
         assert_eq!(p.handle_elapsed(19), None);
         assert_eq!(p.handle_elapsed(1), Some(ReturnControl));
     }
+
+    #[test]
+    fn test_carrier() {
+        let mut p = ReceiverPolicy::new(10, 20, 30, None);
+        assert_eq!(p.handle_carrier_lost(), None);
+        assert_eq!(p.handle_elapsed(10), Some(ChooseTalkgroup));
+
+        // Losing the carrier cuts the watchdog short with the pause timeout.
+        p.enter_traffic();
+        assert_eq!(p.handle_elapsed(5), None);
+        assert_eq!(p.handle_carrier_lost(), None);
+        assert_eq!(p.handle_elapsed(29), None);
+        assert_eq!(p.handle_carrier_lost(), None);
+        assert_eq!(p.handle_elapsed(1), Some(ReturnControl));
+        p.enter_control();
+
+        // Voice resumes after the carrier returns.
+        p.enter_traffic();
+        assert_eq!(p.handle_carrier_lost(), None);
+        assert_eq!(p.handle_elapsed(29), None);
+        assert_eq!(p.handle_nid(
+            NetworkId::new(NetworkAccessCode::Default, VoiceLCFrameGroup)), None);
+        assert_eq!(p.handle_elapsed(19), None);
+        assert_eq!(p.handle_elapsed(1), Some(ReturnControl));
+    }
+
+    #[test]
+    fn test_quality() {
+        use quality::Level;
+
+        let quality = |snr| SignalQuality {
+            level: Level { dbfs: -40.0, snr: snr },
+            deviation: 1800.0,
+            deviation_error: 0.1,
+            symbol_errors: 0.01,
+        };
+
+        let mut p = ReceiverPolicy::new(10, 20, 30, None);
+        p.enter_traffic();
+        assert_eq!(p.handle_quality(&quality(-5.0)), None);
+
+        let mut p = ReceiverPolicy::new(10, 20, 30, Some(6.0));
+        assert_eq!(p.handle_quality(&quality(-5.0)), None);
+
+        p.enter_traffic();
+        assert_eq!(p.handle_quality(&quality(10.0)), None);
+        assert_eq!(p.handle_quality(&quality(5.0)), Some(ReturnControl));
+    }
 }
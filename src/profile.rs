@@ -0,0 +1,120 @@
+//! Named demodulator settings for different systems and channel spacings.
+
+use std::str::FromStr;
+
+use consts::BASEBAND_SAMPLE_RATE;
+use demod::DEVIATION;
+use filter;
+
+/// Number of taps in a runtime-designed channel filter.
+const CHANNEL_TAPS: usize = 65;
+
+/// Channel-select filter applied before demodulation.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ChannelFilter {
+    /// Fixed filter designed for P25.
+    P25,
+    /// Lowpass filter with the contained cutoff frequency (Hz).
+    Lowpass(u32),
+}
+
+impl ChannelFilter {
+    /// Design the coefficients of a runtime lowpass filter, if any.
+    pub fn coefs(&self) -> Option<Vec<f32>> {
+        match *self {
+            ChannelFilter::P25 => None,
+            ChannelFilter::Lowpass(cutoff) => Some(filter::lowpass(CHANNEL_TAPS,
+                cutoff as f32 / BASEBAND_SAMPLE_RATE as f32)),
+        }
+    }
+}
+
+/// Filter applied to the demodulated baseband.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PostFilter {
+    /// Moving average over the contained number of samples.
+    Average(usize),
+    /// Pass the baseband through untouched.
+    Bypass,
+}
+
+/// Demodulator settings.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DemodProfile {
+    /// Name the profile is selected by.
+    pub name: &'static str,
+    /// FM frequency deviation (Hz) scaled to unity.
+    pub deviation: u32,
+    /// Channel-select filter.
+    pub channel: ChannelFilter,
+    /// Baseband filter.
+    pub post: PostFilter,
+}
+
+/// All available profiles, with the default first.
+pub const PROFILES: &'static [DemodProfile] = &[
+    // Standard 12.5kHz P25 channels.
+    DemodProfile {
+        name: "p25",
+        deviation: DEVIATION,
+        channel: ChannelFilter::P25,
+        post: PostFilter::Average(10),
+    },
+    // Tightly packed channels with strong neighbors.
+    DemodProfile {
+        name: "narrow",
+        deviation: DEVIATION,
+        channel: ChannelFilter::Lowpass(4500),
+        post: PostFilter::Average(10),
+    },
+    // Transmitters that wander or overdeviate.
+    DemodProfile {
+        name: "wide",
+        deviation: DEVIATION,
+        channel: ChannelFilter::Lowpass(8000),
+        post: PostFilter::Average(10),
+    },
+    // Unfiltered discriminator output with headroom for overdeviating transmitters, for
+    // diagnostics.
+    DemodProfile {
+        name: "raw",
+        deviation: 7500,
+        channel: ChannelFilter::Lowpass(8000),
+        post: PostFilter::Bypass,
+    },
+];
+
+impl Default for DemodProfile {
+    fn default() -> Self {
+        PROFILES[0]
+    }
+}
+
+impl FromStr for DemodProfile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PROFILES.iter().find(|p| p.name == s).cloned().ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        assert_eq!(DemodProfile::default().name, "p25");
+        assert_eq!("p25".parse(), Ok(DemodProfile::default()));
+        assert_eq!("narrow".parse::<DemodProfile>().unwrap().channel,
+                   ChannelFilter::Lowpass(4500));
+        assert_eq!("bogus".parse::<DemodProfile>(), Err(()));
+
+        for p in PROFILES.iter() {
+            assert_eq!(p.name.parse(), Ok(*p));
+        }
+
+        assert!(ChannelFilter::P25.coefs().is_none());
+        assert_eq!(ChannelFilter::Lowpass(4500).coefs().unwrap().len(), CHANNEL_TAPS);
+    }
+}
@@ -0,0 +1,205 @@
+//! Signal quality measurements.
+
+use std::f32::consts::SQRT_2;
+use std;
+
+use scope::Snapshot;
+use spectrum::decibels;
+
+/// Width (Hz) of a P25 channel.
+const CHANNEL_WIDTH: f32 = 12500.0;
+
+/// Equivalent noise bandwidth (bins) of the spectrum window, which spreads each unit of
+/// power over this many bins.
+const WINDOW_ENBW: f32 = 1.5;
+
+/// Level of a channel measured from the spectrum of the captured I/Q.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Level {
+    /// Power (dBFS) within the channel.
+    pub dbfs: f32,
+    /// Ratio (dB) of the signal within the channel to the noise floor outside it.
+    pub snr: f32,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level {
+            dbfs: decibels(0.0),
+            snr: decibels(0.0),
+        }
+    }
+}
+
+impl Level {
+    /// Measure the channel the given offset (Hz) from the center of the given power
+    /// spectrum, as produced by `Spectrum::power`, of I/Q sampled at the given rate
+    /// (samples/sec).
+    ///
+    /// The noise floor is taken as the median bin outside the channel, so other signals
+    /// in the capture don't count as noise as long as they cover less than half of it.
+    pub fn measure(power: &[f32], rate: u32, offset: f32) -> Self {
+        let width = rate as f32 / power.len() as f32;
+        let center = power.len() as f32 / 2.0 + offset / width;
+
+        let mut signal = 0.0;
+        let mut bins = 0;
+        let mut outside = vec![];
+
+        for (idx, &p) in power.iter().enumerate() {
+            // Leave a guard band around the channel for its filter skirts.
+            match ((idx as f32 - center) * width).abs() {
+                d if d <= CHANNEL_WIDTH / 2.0 => {
+                    signal += p;
+                    bins += 1;
+                },
+                // Garbage samples can make some bins NaN, which don't order.
+                d if d > CHANNEL_WIDTH && p.is_finite() => outside.push(p),
+                _ => {},
+            }
+        }
+
+        outside.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let noise = match outside.get(outside.len() / 2) {
+            Some(&floor) => floor * bins as f32,
+            None => 0.0,
+        };
+
+        Level {
+            dbfs: decibels(signal / WINDOW_ENBW),
+            snr: if noise > 0.0 {
+                decibels((signal - noise).max(0.0) / noise)
+            } else {
+                decibels(0.0)
+            },
+        }
+    }
+}
+
+/// Quality of a received signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SignalQuality {
+    /// Level of the channel.
+    pub level: Level,
+    /// Measured frequency deviation (Hz) of the outer symbols.
+    pub deviation: f32,
+    /// RMS distance of symbols from their ideal levels, as a fraction of the outer
+    /// symbol deviation.
+    pub deviation_error: f32,
+    /// Estimated fraction of symbols decided incorrectly.
+    pub symbol_errors: f32,
+}
+
+impl SignalQuality {
+    /// Combine the given channel level with symbol measurements from the given snapshot
+    /// of baseband with the given FM frequency deviation (Hz) scaled to unity.
+    pub fn new(level: Level, deviation: u32, snap: &Snapshot) -> Self {
+        let mid = snap.thresholds[1];
+
+        // Thresholds sit two thirds of the way out to the outer symbols.
+        let outer = (snap.thresholds[2] - snap.thresholds[0]) * 0.75;
+        let ideal = [mid - outer, mid - outer / 3.0, mid + outer / 3.0, mid + outer];
+
+        let sqerr = snap.symbols.iter().map(|&s| {
+            ideal.iter().map(|&l| (s - l) * (s - l)).fold(std::f32::MAX, f32::min)
+        }).sum::<f32>();
+
+        let rms = (sqerr / snap.symbols.len().max(1) as f32).sqrt();
+
+        // Assuming gaussian noise, a symbol is decided incorrectly when it strays half
+        // the spacing between levels, and the outer symbols have one neighbor rather
+        // than two.
+        let symbol_errors = if rms > 0.0 {
+            (1.5 * erfc(outer / 3.0 / (rms * SQRT_2))).min(1.0)
+        } else {
+            0.0
+        };
+
+        SignalQuality {
+            level: level,
+            deviation: outer * deviation as f32,
+            deviation_error: if outer > 0.0 { rms / outer } else { 1.0 },
+            symbol_errors: symbol_errors,
+        }
+    }
+}
+
+/// Approximate the complementary error function of the given non-negative value.
+fn erfc(x: f32) -> f32 {
+    // Abramowitz and Stegun 7.1.26.
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 +
+        t * (-1.453152027 + t * 1.061405429))));
+
+    poly * (-x * x).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        // Noise floor in every bin, with a channel 10kHz below the center and an
+        // interferer far away.
+        let mut power = vec![1e-6; 100];
+
+        for p in power[36..45].iter_mut() {
+            *p = 1e-3;
+        }
+
+        for p in power[80..90].iter_mut() {
+            *p = 1e-2;
+        }
+
+        let l = Level::measure(&power[..], 100000, -10000.0);
+
+        // 13 bins in the channel, 9 with signal.
+        let signal = 9.0 * 1e-3 + 4.0 * 1e-6;
+        assert!((l.dbfs - decibels(signal / 1.5)).abs() < 1e-3);
+        assert!((l.snr - decibels((signal - 13e-6) / 13e-6)).abs() < 1e-3);
+
+        assert_eq!(Level::measure(&[0.0; 16][..], 100000, 0.0), Level::default());
+
+        // Unusable bins outside the channel are left out of the noise floor.
+        power[0] = std::f32::NAN;
+        power[1] = std::f32::INFINITY;
+
+        let l = Level::measure(&power[..], 100000, -10000.0);
+        assert!((l.snr - decibels((signal - 13e-6) / 13e-6)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_quality() {
+        let level = Level::default();
+
+        let q = SignalQuality::new(level, 5000, &Snapshot {
+            phase: 0,
+            symbols: vec![0.36, 0.12, -0.12, -0.36],
+            thresholds: [-0.24, 0.0, 0.24],
+            eye: vec![],
+        });
+
+        assert!((q.deviation - 1800.0).abs() < 1e-2);
+        assert!(q.deviation_error < 1e-6);
+        assert_eq!(q.symbol_errors, 0.0);
+
+        let q = SignalQuality::new(level, 5000, &Snapshot {
+            phase: 0,
+            symbols: vec![0.40, 0.08, -0.16, -0.32],
+            thresholds: [-0.24, 0.0, 0.24],
+            eye: vec![],
+        });
+
+        assert!((q.deviation_error - 0.04 / 0.36).abs() < 1e-4);
+        assert!(q.symbol_errors > 0.0 && q.symbol_errors < 0.01);
+    }
+
+    #[test]
+    fn test_erfc() {
+        assert!((erfc(0.0) - 1.0).abs() < 1e-6);
+        assert!((erfc(1.0) - 0.157299).abs() < 1e-6);
+        assert!((erfc(2.0) - 0.004678).abs() < 1e-6);
+    }
+}
@@ -1,5 +1,6 @@
 //! Receiver logic.
 
+use std::sync::Arc;
 use std::sync::mpsc::{Sender, Receiver};
 use std;
 
@@ -15,76 +16,171 @@ use throttle::Throttler;
 
 use audio::AudioEvent;
 use hub::{HubEvent, StateEvent};
+use motorola::{self, MotorolaOpcode, ChannelRef, MFG_MOTOROLA};
+use pipeline::{Pipeline, AUDIO_BACKLOG};
 use policy::{ReceiverPolicy, PolicyEvent};
+use quality::{Level, SignalQuality};
+use scope::{Scope, Snapshot};
 use sdr::ControlTaskEvent;
 use talkgroups::TalkgroupSelection;
 
 /// Messages for `RecvTask`.
 pub enum RecvEvent {
     /// Chunk of baseband samples.
-    Baseband(Checkout<Vec<f32>>),
+    Baseband(Baseband),
     /// Change the control channel frequency.
     SetControlFreq(u32),
     /// Reset stat counters.
     ResetStats,
+    /// Retuning the contained chain under the contained tuning generation failed, so
+    /// its samples still come from the previous tuning.
+    TuneFailed(usize, u32),
+    /// The sample source of the contained chain has been exhausted.
+    Finished(usize),
+}
+
+/// Chunk of baseband samples from a receiver chain.
+pub struct Baseband {
+    /// Index of the chain that demodulated the samples.
+    pub chain: usize,
+    /// Generation of the tuning the samples were captured under.
+    pub gen: u32,
+    /// Level of the channel while the samples were captured.
+    pub level: Level,
+    /// FM frequency deviation (Hz) scaled to unity in the samples.
+    pub deviation: u32,
+    /// Whether the channel held a carrier while the samples were captured.
+    pub carrier: bool,
+    /// Baseband samples.
+    pub samples: Checkout<Vec<f32>>,
+}
+
+/// Independently-tuned path from the SDR to a P25 message decoder.
+struct Chain {
+    /// Tuning control for the chain.
+    sdr: Sender<ControlTaskEvent>,
+    /// Receiver state machine.
+    msg: MessageReceiver,
+    /// Policy state machine.
+    policy: ReceiverPolicy,
+    /// Current center frequency (Hz).
+    curfreq: u32,
+    /// Generation of the current tuning, incremented on each retune.
+    gen: u32,
+    /// Whether any samples have been received under the current tuning.
+    tuned: bool,
+    /// Current talkgroup being monitored.
+    curgroup: u16,
+    /// Whether the chain is following a talkgroup.
+    busy: bool,
+    /// Latest level of the channel.
+    level: Level,
+    /// Latest FM frequency deviation (Hz) scaled to unity in the baseband.
+    deviation: u32,
+    /// Captures symbol snapshots for measuring signal quality.
+    scope: Scope,
+}
+
+impl Chain {
+    /// Create a new `Chain` tuned through the given channel and following the given
+    /// policy.
+    fn new(sdr: Sender<ControlTaskEvent>, policy: ReceiverPolicy) -> Self {
+        Chain {
+            sdr: sdr,
+            msg: MessageReceiver::new(),
+            policy: policy,
+            curfreq: std::u32::MAX,
+            gen: 0,
+            tuned: false,
+            curgroup: 0,
+            busy: false,
+            level: Level::default(),
+            deviation: 0,
+            scope: Scope::new(10),
+        }
+    }
 }
 
 /// Processes P25 baseband and performs the duties of a trunking receiver.
+///
+/// With a single chain, the receiver hops that chain between the control channel and
+/// traffic channels. With multiple chains, the first chain stays parked on the control
+/// channel and the rest are assigned to traffic channels.
 pub struct RecvTask {
     /// Receiver events.
     events: Receiver<RecvEvent>,
     /// Event streaming.
     hub: mio_more::channel::Sender<HubEvent>,
-    /// SDR control task.
-    sdr: Sender<ControlTaskEvent>,
     /// Audio output task.
     audio: Sender<AudioEvent>,
     /// Control channel frequency (Hz).
     ctlfreq: u32,
+    /// Candidate control channel frequencies (Hz), hunted through in order.
+    ctlfreqs: Vec<u32>,
+    /// Alternate control channel frequencies (Hz) advertised by the site, hunted through
+    /// after the candidates.
+    alts: Vec<u32>,
+    /// Baseband samples received on the control channel since the last valid TSBK.
+    silence: usize,
+    /// Amount of baseband samples without a valid TSBK before hunting for another
+    /// control channel.
+    hunt_time: usize,
     /// Whether frequency hopping is enabled.
     hopping: bool,
-    /// Receiver state machine.
-    msg: MessageReceiver,
-    /// Policy state machine.
-    policy: ReceiverPolicy,
+    /// Receiver chains, with the first used for the control channel.
+    chains: Vec<Chain>,
+    /// Chain currently feeding audio output, if any.
+    audio_chain: Option<usize>,
     /// Talkgroup selection machinery.
     talkgroups: TalkgroupSelection,
     /// Channel mappings.
     channels: ChannelParamsMap,
-    /// Current center frequency (Hz).
-    curfreq: u32,
-    /// Current talkgroup being monitored.
-    curgroup: u16,
     /// Accumlated statistics.
     stats: Stats,
+    /// Pipeline health counters.
+    pipeline: Arc<Pipeline>,
 }
 
 impl RecvTask {
-    /// Create a new `RecvTask`.
+    /// Create a new `RecvTask` with a chain for each of the given SDR control channels.
+    ///
+    /// The receiver starts on the first of the given candidate control channels and
+    /// hunts through the rest whenever the control channel goes silent for the given
+    /// amount of baseband samples. Voice frames are dropped rather than queued once
+    /// `AUDIO_BACKLOG` of them are waiting in the given pipeline for audio output.
     pub fn new(events: Receiver<RecvEvent>,
                hub: mio_more::channel::Sender<HubEvent>,
-               sdr: Sender<ControlTaskEvent>,
+               sdrs: Vec<Sender<ControlTaskEvent>>,
                audio: Sender<AudioEvent>,
-               ctlfreq: u32,
+               ctlfreqs: Vec<u32>,
+               hunt_time: usize,
                hopping: bool,
                policy: ReceiverPolicy,
-               talkgroups: TalkgroupSelection)
+               talkgroups: TalkgroupSelection,
+               pipeline: Arc<Pipeline>)
         -> Self
     {
+        assert!(!sdrs.is_empty());
+        assert!(!ctlfreqs.is_empty());
+
+        let ctlfreq = ctlfreqs[0];
+
         RecvTask {
             events: events,
             hub: hub,
-            sdr: sdr,
             audio: audio,
             ctlfreq: std::u32::MAX,
+            ctlfreqs: ctlfreqs,
+            alts: vec![],
+            silence: 0,
+            hunt_time: hunt_time,
             hopping: hopping,
-            msg: MessageReceiver::new(),
-            policy: policy,
+            chains: sdrs.into_iter().map(|sdr| Chain::new(sdr, policy.clone())).collect(),
+            audio_chain: None,
             talkgroups: talkgroups,
             channels: ChannelParamsMap::default(),
-            curfreq: std::u32::MAX,
-            curgroup: 0,
             stats: Stats::default(),
+            pipeline: pipeline,
         }.init(ctlfreq)
     }
 
@@ -94,6 +190,11 @@ impl RecvTask {
         self
     }
 
+    /// Whether the control channel has a dedicated chain.
+    fn dedicated(&self) -> bool {
+        self.chains.len() > 1
+    }
+
     /// Change the control channel frequency (Hz).
     ///
     /// This will immediately switch to the new control channel.
@@ -108,66 +209,282 @@ impl RecvTask {
 
         self.hub.send(HubEvent::State(StateEvent::UpdateCtlFreq(freq)))
             .expect("unable to send control frequency");
+
+        for chain in 1..self.chains.len() {
+            self.release(chain);
+        }
+
         self.switch_control();
     }
 
-    /// Move to the control channel.
+    /// Move the first chain to the control channel.
     fn switch_control(&mut self) {
-        self.audio.send(AudioEvent::EndTransmission)
-            .expect("unable to send end of transmission");
+        self.end_audio(0);
+        self.silence = 0;
 
         // FIXME: non-lexical borrowing
         let freq = self.ctlfreq;
-        self.set_freq(freq);
+        self.set_freq(0, freq);
+
+        self.chains[0].busy = false;
+        self.chains[0].policy.enter_control();
+    }
+
+    /// Move on to the control channel after the current one in the candidates followed
+    /// by the advertised alternates.
+    fn hunt(&mut self) {
+        let mut freqs = self.ctlfreqs.clone();
+
+        for &f in self.alts.iter() {
+            if !freqs.contains(&f) {
+                freqs.push(f);
+            }
+        }
+
+        // Retry a control channel that was never tuned, which may have failed
+        // temporarily.
+        if self.hopping && freqs.len() < 2 && !self.chains[0].tuned {
+            info!("control channel was never tuned, retrying {} Hz", self.ctlfreq);
+            self.switch_control();
+            return;
+        }
 
-        self.policy.enter_control();
+        // Hunting is pointless without anywhere else to go.
+        if !self.hopping || freqs.len() < 2 {
+            self.silence = 0;
+            return;
+        }
+
+        // Start from the beginning if the current channel isn't a candidate.
+        let next = match freqs.iter().position(|&f| f == self.ctlfreq) {
+            Some(idx) => (idx + 1) % freqs.len(),
+            None => 0,
+        };
+
+        info!("control channel is silent, hunting on {} Hz", freqs[next]);
+        self.set_control_freq(freqs[next]);
+    }
+
+    /// Stop following the current talkgroup on the given chain.
+    ///
+    /// Without a dedicated control chain, this moves back to the control channel.
+    fn release(&mut self, chain: usize) {
+        if chain == 0 {
+            self.switch_control();
+            return;
+        }
+
+        self.end_audio(chain);
+        self.chains[chain].busy = false;
+        self.chains[chain].curgroup = 0;
+        self.chains[chain].policy.enter_control();
     }
 
-    /// Move to the given frequency (Hz).
-    fn set_freq(&mut self, freq: u32) {
-        debug!("moving to frequency {} Hz", freq);
-        self.curfreq = freq;
+    /// Find a traffic chain that isn't following a talkgroup.
+    fn idle_chain(&self) -> Option<usize> {
+        if !self.dedicated() {
+            return if self.chains[0].busy { None } else { Some(0) };
+        }
+
+        (1..self.chains.len()).find(|&c| !self.chains[c].busy)
+    }
 
-        self.hub.send(HubEvent::UpdateCurFreq(freq))
+    /// Terminate audio output if it's being fed by the given chain.
+    fn end_audio(&mut self, chain: usize) {
+        match self.audio_chain {
+            Some(c) if c != chain => return,
+            _ => {},
+        }
+
+        self.audio_chain = None;
+        self.pipeline.audio.push();
+        self.audio.send(AudioEvent::EndTransmission)
+            .expect("unable to send end of transmission");
+    }
+
+    /// Move the given chain to the given frequency (Hz).
+    fn set_freq(&mut self, chain: usize, freq: u32) {
+        debug!("moving chain {} to frequency {} Hz", chain, freq);
+
+        self.hub.send(HubEvent::State(StateEvent::UpdateCurFreq(chain, freq)))
             .expect("unable to send current frequency");
-        self.sdr.send(ControlTaskEvent::SetFreq(freq))
+
+        let c = &mut self.chains[chain];
+
+        c.curfreq = freq;
+        c.gen = c.gen.wrapping_add(1);
+        c.tuned = false;
+        c.sdr.send(ControlTaskEvent::SetFreq(freq, c.gen))
             .expect("unable to set freq in sdr");
 
-        self.msg.resync();
+        // Samples from the previous channel are dropped, so the decoder can start fresh
+        // with the next accepted chunk.
+        c.msg.resync();
+        c.scope.reset();
     }
 
-    /// Begin processing baseband samples, blocking the current thread.
+    /// Begin processing baseband samples, blocking the current thread until the sample
+    /// source is exhausted.
+    ///
+    /// Baseband from the first chain is passed to the given callback.
     pub fn run<F: FnMut(&[f32])>(&mut self, mut cb: F) {
         let mut stats_notifier = Throttler::new(16);
+        let mut finished = vec![false; self.chains.len()];
 
         loop {
             match self.events.recv().expect("unable to receive baseband") {
-                RecvEvent::Baseband(samples) => {
-                    self.talkgroups.record_elapsed(samples.len());
+                RecvEvent::Baseband(bb) => {
+                    self.pipeline.baseband.pop();
+                    self.handle_baseband(bb, &mut cb);
+                },
+                RecvEvent::SetControlFreq(freq) => {
+                    // A manually chosen channel may belong to a different site.
+                    self.alts.clear();
+                    self.set_control_freq(freq);
+                },
+                RecvEvent::ResetStats => {
+                    self.stats.clear();
+                    self.pipeline.reset();
+                },
+                RecvEvent::TuneFailed(chain, gen) => self.handle_tune_failed(chain, gen),
+                RecvEvent::Finished(chain) => {
+                    finished[chain] = true;
 
-                    for &s in samples.iter() {
-                        self.handle_sample(s);
+                    // Other chains may still have baseband queued.
+                    if finished.iter().all(|&f| f) {
+                        break;
                     }
-
-                    cb(&samples[..]);
-
-                    // FIXME: non-lexical borrowing
-                    let event = self.policy.handle_elapsed(samples.len());
-                    self.handle_policy(event);
                 },
-                RecvEvent::SetControlFreq(freq) => self.set_control_freq(freq),
-                RecvEvent::ResetStats => self.stats.clear(),
             }
 
             stats_notifier.throttle(|| {
-                self.hub.send(HubEvent::UpdateStats(self.stats))
+                self.hub.send(HubEvent::UpdateStats(self.stats, self.pipeline.stats()))
                     .expect("unable to send stats");
             });
         }
+
+        self.hub.send(HubEvent::UpdateStats(self.stats, self.pipeline.stats()))
+            .expect("unable to send stats");
+        self.pipeline.audio.push();
+        self.audio.send(AudioEvent::Finished)
+            .expect("unable to send finish");
+    }
+
+    /// Handle a failed retune of the given chain under the given tuning generation.
+    fn handle_tune_failed(&mut self, chain: usize, gen: u32) {
+        // A later retune supersedes the failed one.
+        if gen != self.chains[chain].gen {
+            return;
+        }
+
+        warn!("chain {} couldn't be tuned to {} Hz", chain, self.chains[chain].curfreq);
+
+        // None of the call's samples will arrive, so give up on it. The control channel
+        // is retried once its silence runs out.
+        if self.chains[chain].busy {
+            self.release(chain);
+        }
+    }
+
+    /// Process the given chunk of baseband samples.
+    fn handle_baseband<F: FnMut(&[f32])>(&mut self, bb: Baseband, cb: &mut F) {
+        let chain = bb.chain;
+
+        // Drop samples captured before the chain's latest retune, but still count the
+        // time they span so timeouts fire even if the retune never takes effect.
+        if bb.gen != self.chains[chain].gen {
+            trace!("dropping stale samples on chain {}", chain);
+
+            if chain == 0 {
+                self.record_control_elapsed(bb.samples.len());
+            }
+
+            if chain == 0 || self.chains[chain].busy {
+                self.handle_elapsed(chain, bb.samples.len());
+            }
+
+            return;
+        }
+
+        self.chains[chain].tuned = true;
+
+        if chain == 0 {
+            self.record_control_elapsed(bb.samples.len());
+            cb(&bb.samples[..]);
+        } else if !self.chains[chain].busy {
+            // Idle traffic chains have nothing to decode.
+            return;
+        }
+
+        self.chains[chain].level = bb.level;
+        self.chains[chain].deviation = bb.deviation;
+
+        if bb.carrier {
+            for &s in bb.samples.iter() {
+                // Snapshots are locked to the decoder, so it must see each sample as it's
+                // decoded.
+                if let Some(snapshot) = self.chains[chain].scope.feed(s) {
+                    self.handle_snapshot(chain, snapshot);
+                }
+
+                self.handle_sample(chain, s);
+            }
+        } else {
+            // There's nothing to decode, and the decoder must find sync again once the
+            // carrier returns.
+            self.chains[chain].msg.resync();
+            self.chains[chain].scope.reset();
+
+            // FIXME: non-lexical borrowing
+            let event = self.chains[chain].policy.handle_carrier_lost();
+            self.handle_policy(chain, event);
+        }
+
+        self.handle_elapsed(chain, bb.samples.len());
+    }
+
+    /// Account for the given number of baseband samples passing on the control chain.
+    fn record_control_elapsed(&mut self, samples: usize) {
+        self.talkgroups.record_elapsed(samples);
+
+        if !self.chains[0].busy {
+            self.silence += samples;
+        }
+    }
+
+    /// Advance the timeouts of the given chain by the given number of baseband samples.
+    fn handle_elapsed(&mut self, chain: usize, samples: usize) {
+        // FIXME: non-lexical borrowing
+        let event = self.chains[chain].policy.handle_elapsed(samples);
+        self.handle_policy(chain, event);
+
+        if self.silence >= self.hunt_time {
+            self.hunt();
+        }
+    }
+
+    /// Measure signal quality with the given symbol snapshot from the given chain.
+    fn handle_snapshot(&mut self, chain: usize, snapshot: Snapshot) {
+        let quality = {
+            let c = &self.chains[chain];
+            SignalQuality::new(c.level, c.deviation, &snapshot)
+        };
+
+        self.hub.send(HubEvent::UpdateQuality(self.chains[chain].curfreq, quality))
+            .expect("unable to send signal quality");
+
+        if chain == 0 {
+            self.hub.send(HubEvent::UpdateSymbols(snapshot))
+                .expect("unable to send symbols");
+        }
+
+        // FIXME: non-lexical borrowing
+        let event = self.chains[chain].policy.handle_quality(&quality);
+        self.handle_policy(chain, event);
     }
 
-    /// Handle the given policy event.
-    fn handle_policy(&mut self, e: Option<PolicyEvent>) {
+    /// Handle the given policy event from the given chain.
+    fn handle_policy(&mut self, chain: usize, e: Option<PolicyEvent>) {
         use self::PolicyEvent::*;
 
         let event = match e {
@@ -176,70 +493,102 @@ impl RecvTask {
         };
 
         match event {
-            Resync => self.msg.resync(),
-            ReturnControl => self.switch_control(),
+            Resync => self.chains[chain].msg.resync(),
+            ReturnControl => self.release(chain),
             ChooseTalkgroup => {
+                let chain = match self.idle_chain() {
+                    Some(c) => c,
+                    None => return,
+                };
+
                 if let Some((tg, freq)) = self.talkgroups.select_idle() {
-                    self.select_talkgroup(tg, freq);
+                    self.select_talkgroup(chain, tg, freq);
                 }
             },
         }
     }
 
-    /// Choose the given talkgroup as the next to monitor.
-    fn select_talkgroup(&mut self, tg: u16, freq: u32) {
+    /// Choose the given talkgroup as the next to monitor on the given chain.
+    fn select_talkgroup(&mut self, chain: usize, tg: u16, freq: u32) {
         if !self.hopping {
             return;
         }
 
-        self.curgroup = tg;
-        self.set_freq(freq);
-        self.policy.enter_traffic();
+        self.set_freq(chain, freq);
+
+        let c = &mut self.chains[chain];
+        c.curgroup = tg;
+        c.busy = true;
+        c.policy.enter_traffic();
 
-        self.hub.send(HubEvent::UpdateTalkGroup(self.curgroup))
+        self.hub.send(HubEvent::State(StateEvent::UpdateTalkGroup(chain, tg)))
             .expect("unable to send talkgroup");
     }
 
-    /// Process the given baseband sample.
-    fn handle_sample(&mut self, s: f32) {
+    /// Process the given baseband sample from the given chain.
+    fn handle_sample(&mut self, chain: usize, s: f32) {
         use p25::message::receiver::MessageEvent::*;
 
-        let event = match self.msg.feed(s) {
+        let event = match self.chains[chain].msg.feed(s) {
             Some(event) => event,
             None => return,
         };
 
-        self.stats.merge(&mut self.msg);
+        self.stats.merge(&mut self.chains[chain].msg);
 
         match event {
             Error(e) => self.stats.record_err(e),
             PacketNID(nid) => {
-                trace!("received NID {:?}", nid.data_unit);
+                trace!("received NID {:?} on chain {}", nid.data_unit, chain);
+
+                self.chains[chain].scope.handle_sync();
 
                 // FIXME: non-lexical borrowing
-                let event = self.policy.handle_nid(nid);
-                self.handle_policy(event);
+                let event = self.chains[chain].policy.handle_nid(nid);
+                self.handle_policy(chain, event);
             },
-            VoiceHeader(head) => self.handle_crypto(head.crypto_alg()),
-            LinkControl(lc) => self.handle_lc(lc),
-            CryptoControl(cc) => self.handle_crypto(cc.alg()),
+            VoiceHeader(head) => self.handle_crypto(chain, head.crypto_alg()),
+            LinkControl(lc) => self.handle_lc(chain, lc),
+            CryptoControl(cc) => self.handle_crypto(chain, cc.alg()),
             LowSpeedDataFragment(_) => {},
             VoiceFrame(vf) => {
+                // Only one chain at a time can feed audio output.
+                match self.audio_chain {
+                    Some(c) if c != chain => return,
+                    _ => self.audio_chain = Some(chain),
+                }
+
+                // Skip voice rather than let audio output fall further behind.
+                if self.pipeline.audio.depth() >= AUDIO_BACKLOG {
+                    self.pipeline.record_audio_drop();
+                    return;
+                }
+
+                self.pipeline.audio.push();
                 self.audio.send(AudioEvent::VoiceFrame(vf))
                     .expect("unable to send voice frame");
             },
-            TrunkingControl(tsbk) => self.handle_tsbk(tsbk),
-            VoiceTerm(lc) => self.handle_lc(lc),
+            TrunkingControl(tsbk) => self.handle_tsbk(chain, tsbk),
+            VoiceTerm(lc) => self.handle_lc(chain, lc),
         }
     }
 
-    /// Process the given trunking packet.
-    fn handle_tsbk(&mut self, tsbk: TsbkFields) {
-        if tsbk.mfg() != 0 {
+    /// Process the given trunking packet from the given chain.
+    fn handle_tsbk(&mut self, chain: usize, tsbk: TsbkFields) {
+        if !tsbk.crc_valid() {
             return;
         }
 
-        if !tsbk.crc_valid() {
+        // Any valid packet shows the control channel is alive.
+        if chain == 0 && !self.chains[0].busy {
+            self.silence = 0;
+        }
+
+        if tsbk.mfg() == MFG_MOTOROLA {
+            return self.handle_motorola(tsbk);
+        }
+
+        if tsbk.mfg() != 0 {
             return;
         }
 
@@ -262,6 +611,9 @@ impl RecvTask {
                 self.handle_traffic_updates(
                     &fields::GroupTrafficUpdate::new(tsbk.payload()));
             },
+            TsbkOpcode::AltControlChannel => {
+                self.add_alternates(&fields::AltControlChannel::new(tsbk.payload()));
+            },
             TsbkOpcode::ChannelParamsUpdate => {
                 let dec = fields::ChannelParamsUpdate::new(tsbk.payload());
                 self.channels.update(&dec);
@@ -273,8 +625,44 @@ impl RecvTask {
         }
     }
 
-    /// Process the given link control word.
-    fn handle_lc(&mut self, lc: LinkControlFields) {
+    /// Process the given Motorola trunking packet.
+    fn handle_motorola(&mut self, tsbk: TsbkFields) {
+        let opcode = match motorola::opcode(&tsbk) {
+            Some(o) => o,
+            None => return,
+        };
+
+        trace!("received Motorola TSBK with opcode {:?}", opcode);
+
+        self.hub.send(HubEvent::MotorolaControl(opcode, tsbk))
+            .expect("unable to send Motorola control");
+
+        match opcode {
+            MotorolaOpcode::PatchAdd => {
+                let p = motorola::PatchGroups::new(tsbk.payload());
+                self.talkgroups.add_patch(p.supergroup(), &p.groups()[..]);
+            },
+            MotorolaOpcode::PatchDelete => {
+                let p = motorola::PatchGroups::new(tsbk.payload());
+                self.talkgroups.remove_patch(p.supergroup(), &p.groups()[..]);
+            },
+            MotorolaOpcode::PatchGrant => {
+                let grant = motorola::PatchGrant::new(tsbk.payload());
+                self.add_supergroup(grant.supergroup(), grant.channel());
+            },
+            MotorolaOpcode::PatchGrantUpdate => {
+                let u = motorola::PatchGrantUpdate::new(tsbk.payload());
+
+                for &(ch, sg) in u.updates().iter() {
+                    self.add_supergroup(sg, ch);
+                }
+            },
+            MotorolaOpcode::BaseStationId => {},
+        }
+    }
+
+    /// Process the given link control word from the given chain.
+    fn handle_lc(&mut self, chain: usize, lc: LinkControlFields) {
         use p25::voice::control::LinkControlOpcode;
 
         let opcode = match lc.opcode() {
@@ -290,15 +678,15 @@ impl RecvTask {
         match opcode {
             LinkControlOpcode::CallTermination => {
                 // FIXME: non-lexical borrowing
-                let event = self.policy.handle_call_term();
-                self.handle_policy(event);
+                let event = self.chains[chain].policy.handle_call_term();
+                self.handle_policy(chain, event);
             },
             LinkControlOpcode::GroupVoiceUpdate => {
                 self.handle_traffic_updates(
                     &fields::GroupTrafficUpdate::new(lc.payload()));
 
                 if let Some((tg, freq)) = self.talkgroups.select_preempt() {
-                    self.select_talkgroup(tg, freq);
+                    self.select_talkgroup(chain, tg, freq);
                 }
             },
             _ => {},
@@ -312,20 +700,37 @@ impl RecvTask {
         }
     }
 
-    /// Process the given encryption info for the current talkgroup.
-    fn handle_crypto(&mut self, alg: CryptoAlgorithm) {
+    /// Process the given encryption info for the current talkgroup of the given chain.
+    fn handle_crypto(&mut self, chain: usize, alg: CryptoAlgorithm) {
         if let CryptoAlgorithm::Unencrypted = alg {
             return;
         }
 
-        self.switch_control();
-        self.talkgroups.record_encrypted(self.curgroup, alg);
+        let tg = self.chains[chain].curgroup;
+
+        self.release(chain);
+        self.talkgroups.record_encrypted(tg, alg);
 
         self.hub.send(
-            HubEvent::State(StateEvent::UpdateEncrypted(self.curgroup, alg))
+            HubEvent::State(StateEvent::UpdateEncrypted(tg, alg))
         ).expect("unable to send encrypted talkgroups");
     }
 
+    /// Keep the alternate control channels advertised in the given packet.
+    fn add_alternates(&mut self, f: &fields::AltControlChannel) {
+        for &(ch, _) in f.alts().iter() {
+            let freq = match self.channels.lookup(ch.id()) {
+                Some(p) => p.rx_freq(ch.number()),
+                None => continue,
+            };
+
+            if !self.alts.contains(&freq) {
+                debug!("found alternate control channel {} Hz", freq);
+                self.alts.push(freq);
+            }
+        }
+    }
+
     /// Collect the given talkgroup and associated traffic channel.
     fn add_talkgroup(&mut self, tg: TalkGroup, ch: Channel) {
         let tg = match tg {
@@ -338,6 +743,462 @@ impl RecvTask {
             None => return,
         };
 
-        self.talkgroups.add_talkgroup(tg, freq);
+        self.collect_talkgroup(tg, freq);
+    }
+
+    /// Collect the given supergroup and associated traffic channel.
+    fn add_supergroup(&mut self, sg: u16, (id, number): ChannelRef) {
+        // Unused update slots are zeroed.
+        if sg == 0 {
+            return;
+        }
+
+        let freq = match self.channels.lookup(id) {
+            Some(p) => p.rx_freq(number),
+            None => return,
+        };
+
+        self.collect_talkgroup(sg, freq);
+    }
+
+    /// Consider the given talkgroup on the given traffic channel (Hz) for selection,
+    /// unless a chain is already following it.
+    ///
+    /// Candidates are cleared on every selection, so filtering them as they're collected
+    /// keeps a call that's still being advertised from being assigned to a second chain.
+    fn collect_talkgroup(&mut self, tg: u16, freq: u32) {
+        let followed = self.chains.iter().any(|c| {
+            c.busy && (c.curgroup == tg || c.curfreq == freq)
+        });
+
+        if !followed {
+            self.talkgroups.add_talkgroup(tg, freq);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use pool::Pool;
+    use consts::BASEBAND_SAMPLE_RATE;
+    use demod::DEVIATION;
+    use simulator::{Call, Site};
+
+    /// Baseband samples per second.
+    const SEC: usize = BASEBAND_SAMPLE_RATE as usize;
+
+    /// Baseband samples in each chunk fed to the receiver.
+    const CHUNK: usize = SEC / 10;
+
+    /// Talkgroup selection timeout.
+    const SELECT: usize = SEC / 2;
+
+    /// Traffic channel watchdog timeout.
+    const WATCHDOG: usize = SEC;
+
+    /// Call termination pause timeout.
+    const PAUSE: usize = SEC / 4;
+
+    /// Control channel frequency (Hz) of the simulated site.
+    const CTLFREQ: u32 = 851_012_500;
+
+    /// Drives a `RecvTask` with baseband from a simulated site, retuning each simulated
+    /// chain whenever the receiver does.
+    struct Harness {
+        /// Receiver under test.
+        task: RecvTask,
+        /// Simulated site.
+        site: Site,
+        /// Tuning control for each chain.
+        tuners: Vec<Receiver<ControlTaskEvent>>,
+        /// Current frequency (Hz) and tuning generation of each chain.
+        tuned: Vec<(u32, u32)>,
+        /// Every baseband sample and frequency (Hz) each chain was tuned at.
+        tunes: Vec<Vec<(usize, u32)>>,
+        /// Current baseband sample.
+        time: usize,
+        /// Frequency (Hz) that every tune to fails, if any, and whether the failures are
+        /// reported to the receiver.
+        failing: Option<(u32, bool)>,
+        /// Number of chunks from the previous frequency that arrive tagged with each new
+        /// tuning, like the backlog buffered by an rtl_tcp server.
+        backlog: usize,
+        /// Previous frequency (Hz) of each chain and the number of its chunks left to
+        /// arrive under the current tuning.
+        pending: Vec<(u32, usize)>,
+        /// Baseband buffers.
+        pool: Pool<Vec<f32>>,
+        /// Events streamed from the receiver.
+        hub: mio_more::channel::Receiver<HubEvent>,
+        /// Audio output from the receiver.
+        _audio: Receiver<AudioEvent>,
+    }
+
+    impl Harness {
+        /// Create a new `Harness` with the given number of chains receiving the given
+        /// site.
+        fn new(chains: usize, site: Site) -> Self {
+            let (_tx_events, rx_events) = channel();
+            let (tx_hub, rx_hub) = mio_more::channel::channel();
+            let (tx_audio, rx_audio) = channel();
+
+            let (sdrs, tuners): (Vec<_>, Vec<_>) = (0..chains).map(|_| channel()).unzip();
+
+            let task = RecvTask::new(rx_events, tx_hub, sdrs, tx_audio, vec![CTLFREQ],
+                std::usize::MAX, true, ReceiverPolicy::new(SELECT, WATCHDOG, PAUSE, None),
+                TalkgroupSelection::default(), Arc::new(Pipeline::default()));
+
+            let mut h = Harness {
+                task: task,
+                site: site,
+                tuners: tuners,
+                tuned: vec![(std::u32::MAX, 0); chains],
+                tunes: vec![vec![]; chains],
+                time: 0,
+                failing: None,
+                backlog: 0,
+                pending: vec![(std::u32::MAX, 0); chains],
+                pool: Pool::with_capacity(chains, || vec![0.0; CHUNK]),
+                hub: rx_hub,
+                _audio: rx_audio,
+            };
+
+            h.retune();
+            h
+        }
+
+        /// Feed every chain baseband from its current frequency until the given
+        /// baseband sample.
+        fn run(&mut self, until: usize) {
+            while self.time < until {
+                for chain in 0..self.tuned.len() {
+                    let (mut freq, gen) = self.tuned[chain];
+
+                    if self.pending[chain].1 > 0 {
+                        freq = self.pending[chain].0;
+                        self.pending[chain].1 -= 1;
+                    }
+
+                    let mut samples = self.pool.checkout()
+                        .expect("unable to allocate baseband");
+                    samples.copy_from_slice(
+                        &self.site.baseband(freq, self.time, CHUNK)[..]);
+
+                    self.task.handle_baseband(Baseband {
+                        chain: chain,
+                        gen: gen,
+                        level: Level::default(),
+                        deviation: DEVIATION,
+                        carrier: true,
+                        samples: samples,
+                    }, &mut |_: &[f32]| {});
+
+                    self.retune();
+                }
+
+                self.time += CHUNK;
+            }
+        }
+
+        /// Apply any tuning requested by the receiver.
+        fn retune(&mut self) {
+            for (chain, tuner) in self.tuners.iter().enumerate() {
+                for e in tuner.try_iter() {
+                    let (freq, gen) = match e {
+                        ControlTaskEvent::SetFreq(freq, gen) => (freq, gen),
+                        _ => continue,
+                    };
+
+                    self.tunes[chain].push((self.time, freq));
+
+                    match self.failing {
+                        Some((f, true)) if f == freq =>
+                            self.task.handle_tune_failed(chain, gen),
+                        Some((f, false)) if f == freq => {},
+                        _ => {
+                            self.pending[chain] = (self.tuned[chain].0, self.backlog);
+                            self.tuned[chain] = (freq, gen);
+                        },
+                    }
+                }
+            }
+        }
+
+        /// Frequencies (Hz) the given chain was requested to tune to, in order.
+        fn freqs(&self, chain: usize) -> Vec<u32> {
+            self.tunes[chain].iter().map(|&(_, f)| f).collect()
+        }
+
+        /// Talkgroups the receiver has announced following, in order.
+        fn talkgroups(&self) -> Vec<u16> {
+            let mut tgs = vec![];
+
+            while let Ok(e) = self.hub.try_recv() {
+                if let HubEvent::State(StateEvent::UpdateTalkGroup(_, tg)) = e {
+                    tgs.push(tg);
+                }
+            }
+
+            tgs
+        }
+    }
+
+    #[test]
+    fn test_finished() {
+        let (tx_events, rx_events) = channel();
+        let (tx_hub, _rx_hub) = mio_more::channel::channel();
+        let (tx_audio, rx_audio) = channel();
+        let (sdrs, _tuners): (Vec<_>, Vec<_>) = (0..2).map(|_| channel()).unzip();
+        let pipeline = Arc::new(Pipeline::default());
+
+        let mut task = RecvTask::new(rx_events, tx_hub, sdrs, tx_audio, vec![CTLFREQ],
+            std::usize::MAX, true, ReceiverPolicy::new(SELECT, WATCHDOG, PAUSE, None),
+            TalkgroupSelection::default(), pipeline.clone());
+
+        let mut pool = Pool::with_capacity(1, || vec![0.0; CHUNK]);
+
+        // The control chain still has baseband queued when the traffic chain finishes.
+        tx_events.send(RecvEvent::Finished(1)).unwrap();
+        pipeline.baseband.push();
+        tx_events.send(RecvEvent::Baseband(Baseband {
+            chain: 0,
+            gen: task.chains[0].gen,
+            level: Level::default(),
+            deviation: DEVIATION,
+            carrier: true,
+            samples: pool.checkout().unwrap(),
+        })).unwrap();
+        tx_events.send(RecvEvent::Finished(0)).unwrap();
+
+        let mut received = 0;
+        task.run(|samples: &[f32]| received += samples.len());
+
+        assert_eq!(received, CHUNK);
+        assert!(rx_audio.try_iter().any(|e| match e {
+            AudioEvent::Finished => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_sim_call_termination() {
+        let call = Call {
+            talkgroup: 0x101,
+            channel: 10,
+            start: SEC,
+            ldus: 3,
+            transmit: true,
+            encrypted: false,
+        };
+
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &[call], 4 * SEC));
+        h.run(4 * SEC);
+
+        // Follows the grant to the traffic channel and returns once the call ends.
+        assert_eq!(h.freqs(0), vec![CTLFREQ, Site::traffic_freq(10), CTLFREQ]);
+        assert_eq!(h.talkgroups(), vec![0x101]);
+
+        // Returns after the pause following the terminator rather than waiting out the
+        // watchdog.
+        let (hop, _) = h.tunes[0][1];
+        let (ret, _) = h.tunes[0][2];
+
+        assert!(hop > call.start && hop < call.end());
+        assert!(ret >= call.end() && ret <= call.end() + PAUSE + 2 * CHUNK);
+    }
+
+    #[test]
+    fn test_sim_watchdog() {
+        // Granted call that never shows up on the traffic channel.
+        let call = Call {
+            talkgroup: 0x101,
+            channel: 10,
+            start: SEC,
+            ldus: 2,
+            transmit: false,
+            encrypted: false,
+        };
+
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &[call], 4 * SEC));
+        h.run(4 * SEC);
+
+        assert_eq!(h.freqs(0), vec![CTLFREQ, Site::traffic_freq(10), CTLFREQ]);
+
+        let (hop, _) = h.tunes[0][1];
+        let (ret, _) = h.tunes[0][2];
+
+        assert!(ret >= hop + WATCHDOG && ret <= hop + WATCHDOG + 2 * CHUNK);
+    }
+
+    #[test]
+    fn test_sim_backlog() {
+        let call = Call {
+            talkgroup: 0x101,
+            channel: 10,
+            start: SEC,
+            ldus: 3,
+            transmit: true,
+            encrypted: false,
+        };
+
+        // An rtl_tcp retune still delivers buffered control channel samples as if they
+        // were from the traffic channel.
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &[call], 4 * SEC));
+        h.backlog = 2;
+        h.run(4 * SEC);
+
+        // The leftover packets are skipped, and the call is followed as usual.
+        assert_eq!(h.freqs(0), vec![CTLFREQ, Site::traffic_freq(10), CTLFREQ]);
+        assert_eq!(h.talkgroups(), vec![0x101]);
+
+        let (ret, _) = h.tunes[0][2];
+        assert!(ret >= call.end() && ret <= call.end() + PAUSE + 2 * CHUNK);
+    }
+
+    #[test]
+    fn test_sim_tune_failed() {
+        let call = Call {
+            talkgroup: 0x101,
+            channel: 10,
+            start: SEC,
+            ldus: 8,
+            transmit: true,
+            encrypted: false,
+        };
+
+        // Stale samples from the control channel still let the watchdog fire.
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &[call], 4 * SEC));
+        h.failing = Some((Site::traffic_freq(10), false));
+        h.run(3 * SEC);
+
+        assert_eq!(&h.freqs(0)[..3], &[CTLFREQ, Site::traffic_freq(10), CTLFREQ][..]);
+
+        let (hop, _) = h.tunes[0][1];
+        let (ret, _) = h.tunes[0][2];
+
+        assert!(ret >= hop + WATCHDOG && ret <= hop + WATCHDOG + 2 * CHUNK);
+
+        // A reported failure returns to the control channel right away.
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &[call], 4 * SEC));
+        h.failing = Some((Site::traffic_freq(10), true));
+        h.run(3 * SEC);
+
+        assert_eq!(&h.freqs(0)[..3], &[CTLFREQ, Site::traffic_freq(10), CTLFREQ][..]);
+        assert_eq!(h.tunes[0][2].0, h.tunes[0][1].0);
+    }
+
+    #[test]
+    fn test_sim_encrypted() {
+        let calls = [
+            Call { talkgroup: 0x202, channel: 20, start: SEC, ldus: 4, transmit: true,
+                   encrypted: true },
+            Call { talkgroup: 0x101, channel: 10, start: 2 * SEC, ldus: 2, transmit: true,
+                   encrypted: false },
+        ];
+
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &calls[..], 5 * SEC));
+        h.run(5 * SEC);
+
+        // The encrypted call is followed until its encryption sync is decoded, then
+        // never again while it's still advertised.
+        assert_eq!(h.freqs(0), vec![CTLFREQ, Site::traffic_freq(20), CTLFREQ,
+                                    Site::traffic_freq(10), CTLFREQ]);
+
+        let (hop, _) = h.tunes[0][1];
+        let (ret, _) = h.tunes[0][2];
+
+        assert!(ret < hop + WATCHDOG);
+        assert!(ret < calls[0].end());
+
+        let mut followed = vec![];
+        let mut encrypted = vec![];
+
+        while let Ok(e) = h.hub.try_recv() {
+            match e {
+                HubEvent::State(StateEvent::UpdateTalkGroup(_, tg)) =>
+                    followed.push(tg),
+                HubEvent::State(StateEvent::UpdateEncrypted(tg, _)) => encrypted.push(tg),
+                _ => {},
+            }
+        }
+
+        assert_eq!(followed, vec![0x202, 0x101]);
+        assert_eq!(encrypted, vec![0x202]);
+    }
+
+    #[test]
+    fn test_sim_preempt() {
+        let calls = [
+            Call { talkgroup: 0x101, channel: 10, start: SEC / 2, ldus: 10,
+                   transmit: true, encrypted: false },
+            Call { talkgroup: 0x303, channel: 30, start: 2 * SEC, ldus: 3,
+                   transmit: true, encrypted: false },
+        ];
+
+        let mut h = Harness::new(1, Site::new(CTLFREQ, &calls[..], 6 * SEC));
+        h.task.talkgroups.add_preempt(0x303);
+        h.run(6 * SEC);
+
+        // Link control on the first call's traffic channel advertises the preempting
+        // call, which is switched to straight from the traffic channel.
+        assert_eq!(h.freqs(0)[..3], [CTLFREQ, Site::traffic_freq(10),
+                                     Site::traffic_freq(30)]);
+        assert_eq!(h.talkgroups()[..2], [0x101, 0x303]);
+
+        let (preempt, _) = h.tunes[0][2];
+
+        assert!(preempt > calls[1].start);
+        assert!(preempt < calls[0].end());
+    }
+
+    #[test]
+    fn test_sim_dedicated() {
+        let calls = [
+            Call { talkgroup: 0x101, channel: 10, start: SEC / 2, ldus: 3, transmit: true,
+                   encrypted: false },
+            Call { talkgroup: 0x202, channel: 20, start: 3 * SEC / 2, ldus: 6,
+                   transmit: true, encrypted: false },
+        ];
+
+        let mut h = Harness::new(2, Site::new(CTLFREQ, &calls[..], 7 * SEC));
+        h.run(7 * SEC);
+
+        // Control chain stays put while the traffic chain follows the first call, then
+        // picks up the second, still advertised, once the first ends.
+        assert_eq!(h.freqs(0), vec![CTLFREQ]);
+        assert_eq!(h.freqs(1)[0], Site::traffic_freq(10));
+        assert_eq!(h.talkgroups()[0], 0x101);
+
+        // The first call's grants may keep it a candidate for a while after it ends.
+        let &(second, _) = h.tunes[1].iter()
+            .find(|&&(_, f)| f == Site::traffic_freq(20))
+            .expect("second call not followed");
+
+        assert!(second >= calls[0].end());
+        assert!(!h.task.chains[1].busy);
+    }
+
+    #[test]
+    fn test_sim_followed_once() {
+        let call = Call {
+            talkgroup: 0x101,
+            channel: 10,
+            start: SEC / 2,
+            ldus: 8,
+            transmit: true,
+            encrypted: false,
+        };
+
+        let mut h = Harness::new(3, Site::new(CTLFREQ, &[call], 5 * SEC));
+        h.run(5 * SEC);
+
+        // The call keeps being advertised while the first traffic chain follows it, but
+        // the second traffic chain is never sent to it.
+        assert_eq!(h.freqs(1), vec![Site::traffic_freq(10)]);
+        assert!(h.freqs(2).is_empty());
+        assert_eq!(h.talkgroups(), vec![0x101]);
+        assert!(!h.task.chains[1].busy);
     }
 }
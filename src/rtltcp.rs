@@ -0,0 +1,153 @@
+//! Client for the `rtl_tcp` network protocol.
+//!
+//! On connection, the server sends a 12-byte header, followed by a continuous stream of
+//! interleaved 8-bit I/Q samples. The client controls the remote tuner by sending 5-byte
+//! commands, each an opcode byte followed by a big-endian 32-bit parameter.
+
+use std::io::{Read, Write, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std;
+
+use consts::BUF_BYTES;
+use sdr::{SampleSource, Tuner};
+
+/// Magic bytes at the start of the server header.
+const MAGIC: &'static [u8] = b"RTL0";
+
+/// Commands understood by the server.
+#[derive(Copy, Clone)]
+enum Command {
+    /// Set center frequency (Hz).
+    SetFreq = 0x01,
+    /// Set sample rate (samples/sec).
+    SetSampleRate = 0x02,
+    /// Set automatic (0) or manual (1) gain mode.
+    SetGainMode = 0x03,
+    /// Set manual tuner gain (tenths of a dB).
+    SetGain = 0x04,
+    /// Set frequency correction (PPM).
+    SetPpm = 0x05,
+}
+
+/// Connect to the `rtl_tcp` server at the given address.
+pub fn connect<A: ToSocketAddrs>(addr: A)
+    -> std::io::Result<(RtlTcpTuner, RtlTcpSource)>
+{
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+
+    let mut header = [0; 12];
+    stream.read_exact(&mut header)?;
+
+    if &header[..4] != MAGIC {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "invalid rtl_tcp header"));
+    }
+
+    info!("connected to rtl_tcp with tuner type {} and {} gains",
+        u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+        u32::from_be_bytes([header[8], header[9], header[10], header[11]]));
+
+    Ok((RtlTcpTuner(stream.try_clone()?), RtlTcpSource(stream)))
+}
+
+/// Sample source reading from an `rtl_tcp` server.
+pub struct RtlTcpSource(TcpStream);
+
+impl SampleSource for RtlTcpSource {
+    fn read(&mut self, cb: &mut dyn FnMut(&[u8])) -> std::io::Result<()> {
+        let mut buf = vec![0; BUF_BYTES];
+
+        loop {
+            self.0.read_exact(&mut buf[..])?;
+            cb(&buf[..]);
+        }
+    }
+}
+
+/// Tuner controlling an `rtl_tcp` server.
+pub struct RtlTcpTuner(TcpStream);
+
+impl RtlTcpTuner {
+    /// Send the given command and parameter to the server.
+    fn send(&mut self, cmd: Command, param: u32) -> std::io::Result<()> {
+        let p = param.to_be_bytes();
+        self.0.write_all(&[cmd as u8, p[0], p[1], p[2], p[3]])
+    }
+}
+
+impl Tuner for RtlTcpTuner {
+    fn set_center_freq(&mut self, freq: u32) -> std::io::Result<()> {
+        self.send(Command::SetFreq, freq)
+    }
+
+    fn set_sample_rate(&mut self, rate: u32) -> std::io::Result<()> {
+        self.send(Command::SetSampleRate, rate)
+    }
+
+    fn set_ppm(&mut self, ppm: i32) -> std::io::Result<()> {
+        self.send(Command::SetPpm, ppm as u32)
+    }
+
+    fn set_tuner_gain(&mut self, gain: i32) -> std::io::Result<()> {
+        // Switch to manual gain mode before setting the gain.
+        self.send(Command::SetGainMode, 1)?;
+        self.send(Command::SetGain, gain as u32)
+    }
+
+    fn enable_agc(&mut self) -> std::io::Result<()> {
+        self.send(Command::SetGainMode, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_loopback() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut s, _) = server.accept().unwrap();
+
+            s.write_all(b"RTL0\x00\x00\x00\x05\x00\x00\x00\x1d").unwrap();
+            s.write_all(&vec![0x80; BUF_BYTES * 2 + 10]).unwrap();
+
+            let mut cmds = [0; 5 * 6];
+            s.read_exact(&mut cmds).unwrap();
+
+            cmds
+        });
+
+        let (mut tuner, mut source) = connect(addr).unwrap();
+
+        tuner.set_sample_rate(240000).unwrap();
+        tuner.set_ppm(-2).unwrap();
+        tuner.set_tuner_gain(280).unwrap();
+        tuner.enable_agc().unwrap();
+        tuner.set_center_freq(856162500).unwrap();
+
+        let mut chunks = 0;
+
+        // The trailing partial chunk should cause an error once the server disconnects.
+        assert!(source.read(&mut |buf: &[u8]| {
+            assert_eq!(buf.len(), BUF_BYTES);
+            assert!(buf.iter().all(|&b| b == 0x80));
+            chunks += 1;
+        }).is_err());
+
+        assert_eq!(chunks, 2);
+
+        assert_eq!(&handle.join().unwrap()[..], &[
+            0x02, 0x00, 0x03, 0xa9, 0x80,
+            0x05, 0xff, 0xff, 0xff, 0xfe,
+            0x03, 0x00, 0x00, 0x00, 0x01,
+            0x04, 0x00, 0x00, 0x01, 0x18,
+            0x03, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x33, 0x08, 0x00, 0xc4,
+        ][..]);
+    }
+}
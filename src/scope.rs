@@ -0,0 +1,256 @@
+//! Symbol constellation and eye diagram snapshots for tuning diagnostics.
+
+use std::collections::VecDeque;
+
+use consts::BASEBAND_SAMPLE_RATE;
+
+/// Baseband samples per C4FM symbol.
+const SPS: usize = (BASEBAND_SAMPLE_RATE / 4800) as usize;
+
+/// Frame synchronization word that begins every data unit.
+const FRAME_SYNC: u64 = 0x5575F5FF77FF;
+
+/// Number of symbols in the frame sync.
+const SYNC_SYMBOLS: usize = 24;
+
+/// Number of symbols from the start of the frame sync through the end of the NID,
+/// including a status symbol.
+const SYNC_NID_SYMBOLS: usize = 57;
+
+/// Number of baseband samples kept to capture a frame sync and NID.
+const HISTORY: usize = (SYNC_NID_SYMBOLS + 1) * SPS;
+
+/// Number of symbols captured in each snapshot.
+const SNAPSHOT_SYMBOLS: usize = 480;
+
+/// Number of two-symbol traces included in each eye diagram.
+const EYE_TRACES: usize = 32;
+
+/// Symbol values and decision thresholds from a stretch of C4FM baseband.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// Baseband sample, within each symbol period, where the decoder decides symbols.
+    pub phase: usize,
+    /// Baseband value at each symbol center.
+    pub symbols: Vec<f32>,
+    /// Decision thresholds between the -3/-1, -1/+1, and +1/+3 symbols.
+    pub thresholds: [f32; 3],
+    /// Traces of baseband spanning the symbol period on either side of a symbol center.
+    pub eye: Vec<Vec<f32>>,
+}
+
+/// Periodically captures C4FM baseband at the symbol decider's sampling instants.
+///
+/// The message decoder doesn't expose its symbol decider, so captures are locked to it
+/// instead: each begins at the frame sync of a NID the decoder has just reported, whose
+/// last symbol it decided at the latest sample. Symbols are taken at the decoder's own
+/// sampling instants, and thresholds come from the outer symbol levels of that frame
+/// sync, from which the decoder calibrates its decider.
+pub struct Scope {
+    /// Most recent baseband, covering at least a frame sync and NID.
+    history: VecDeque<f32>,
+    /// Baseband captured for the current snapshot, if one is in progress.
+    buf: Vec<f32>,
+    /// Whether a snapshot is being captured.
+    capturing: bool,
+    /// Sample, within each symbol period, of the decoder's sampling instants.
+    phase: usize,
+    /// Baseband samples fed in so far.
+    time: usize,
+    /// Number of snapshot windows between captures.
+    interval: usize,
+    /// Samples to wait before another capture can begin.
+    skip: usize,
+}
+
+impl Scope {
+    /// Create a new `Scope` that captures at most one in every given number of windows
+    /// of baseband.
+    pub fn new(interval: usize) -> Self {
+        Scope {
+            history: VecDeque::with_capacity(HISTORY),
+            buf: Vec::with_capacity(SNAPSHOT_SYMBOLS * SPS),
+            capturing: false,
+            phase: 0,
+            time: 0,
+            interval: interval,
+            skip: 0,
+        }
+    }
+
+    /// Feed in a baseband sample, returning a snapshot if a capture was completed.
+    pub fn feed(&mut self, s: f32) -> Option<Snapshot> {
+        self.time = self.time.wrapping_add(1);
+        self.skip = self.skip.saturating_sub(1);
+
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(s);
+
+        if !self.capturing {
+            return None;
+        }
+
+        self.buf.push(s);
+
+        if self.buf.len() < self.buf.capacity() {
+            return None;
+        }
+
+        let snapshot = snapshot(&self.buf[..], self.phase);
+
+        self.buf.clear();
+        self.capturing = false;
+        self.skip = (self.interval - 1) * SNAPSHOT_SYMBOLS * SPS;
+
+        Some(snapshot)
+    }
+
+    /// Record that the decoder decided the last symbol of a NID at the latest sample.
+    pub fn handle_sync(&mut self) {
+        if self.capturing || self.skip > 0 {
+            return;
+        }
+
+        let phase = self.time.wrapping_sub(1) % SPS;
+
+        // Start from the symbol period holding the first frame sync symbol.
+        let len = (SYNC_NID_SYMBOLS - 1) * SPS + phase + 1;
+
+        if self.history.len() < len {
+            return;
+        }
+
+        let start = self.history.len() - len;
+
+        self.buf.extend(self.history.iter().skip(start));
+        self.phase = phase;
+        self.capturing = true;
+    }
+
+    /// Discard captured baseband, such as after a retune.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.buf.clear();
+        self.capturing = false;
+    }
+}
+
+/// Build a snapshot from the given window of baseband, which begins with a frame sync
+/// sampled at the given symbol phase.
+fn snapshot(buf: &[f32], phase: usize) -> Snapshot {
+    let symbols: Vec<f32> = buf[phase..].iter().step_by(SPS).cloned().collect();
+
+    // Frame sync only holds +3 (01) and -3 (11) symbols.
+    let level = |dibit| {
+        let vals: Vec<f32> = (0..SYNC_SYMBOLS)
+            .filter(|&n| FRAME_SYNC >> ((SYNC_SYMBOLS - 1 - n) * 2) & 0b11 == dibit)
+            .map(|n| symbols[n])
+            .collect();
+
+        mean(&vals[..])
+    };
+
+    let high = level(0b01);
+    let low = level(0b11);
+    let mid = (high + low) / 2.0;
+
+    let eye = (1..symbols.len()).map(|n| n * SPS + phase)
+        .filter(|&c| c + SPS < buf.len())
+        .take(EYE_TRACES)
+        .map(|c| buf[c - SPS..c + SPS + 1].to_vec())
+        .collect();
+
+    Snapshot {
+        phase: phase,
+        symbols: symbols,
+        // The ±1 symbols sit at a third of the ±3 deviation, so the outer thresholds
+        // fall two thirds of the way out.
+        thresholds: [
+            mid + (low - mid) * 2.0 / 3.0,
+            mid,
+            mid + (high - mid) * 2.0 / 3.0,
+        ],
+        eye: eye,
+    }
+}
+
+/// Calculate the mean of the given values.
+fn mean(vals: &[f32]) -> f32 {
+    if vals.is_empty() {
+        return 0.0;
+    }
+
+    vals.iter().sum::<f32>() / vals.len() as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scope() {
+        let levels = [0.36, 0.12, -0.12, -0.36];
+        let mut dibits = vec![];
+
+        // Blocks of a frame sync followed by other symbols.
+        for _ in 0..12 {
+            dibits.extend((0..SYNC_SYMBOLS).rev()
+                .map(|n| (FRAME_SYNC >> (n * 2)) as usize & 0b11));
+            dibits.extend((0..200 - SYNC_SYMBOLS).map(|n| n * 7 % 4));
+        }
+
+        let level = |d: usize| match d {
+            0b01 => levels[0],
+            0b00 => levels[1],
+            0b10 => levels[2],
+            _ => levels[3],
+        };
+
+        let mut scope = Scope::new(2);
+        let mut snapshots = vec![];
+
+        // Each symbol peaks 3 samples into its period, where the decoder samples it.
+        for n in 0..dibits.len() * SPS {
+            let dist = (n % SPS) as f32 - 3.0;
+            let sample = level(dibits[n / SPS]) * (1.0 - dist.abs() / SPS as f32);
+
+            if let Some(s) = scope.feed(sample) {
+                snapshots.push(s);
+            }
+
+            if n % (200 * SPS) == (SYNC_NID_SYMBOLS - 1) * SPS + 3 {
+                scope.handle_sync();
+            }
+        }
+
+        // Captures begin at the first sync after waiting out every other window.
+        assert_eq!(snapshots.len(), 2);
+
+        let s = &snapshots[0];
+
+        assert_eq!(s.phase, 3);
+        assert_eq!(s.symbols.len(), SNAPSHOT_SYMBOLS);
+        assert_eq!(s.symbols[..4], [0.36, 0.36, 0.36, 0.36]);
+        assert_eq!(s.symbols[SYNC_SYMBOLS - 1], -0.36);
+        assert!((s.thresholds[0] + 0.24).abs() < 1e-4);
+        assert!(s.thresholds[1].abs() < 1e-4);
+        assert!((s.thresholds[2] - 0.24).abs() < 1e-4);
+        assert_eq!(s.eye.len(), EYE_TRACES);
+        assert_eq!(s.eye[0].len(), SPS * 2 + 1);
+        assert_eq!(s.eye[0][SPS], s.symbols[1]);
+
+        // Retuning discards the frame sync.
+        let mut scope = Scope::new(1);
+
+        for n in 0..HISTORY {
+            scope.feed(level(dibits[n / SPS]));
+        }
+
+        scope.reset();
+        scope.handle_sync();
+        assert!(!scope.capturing);
+    }
+}
@@ -1,59 +1,464 @@
 //! Interface to RTL-SDR.
 
-use std::sync::mpsc::{Sender, Receiver};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender, SyncSender, Receiver, TrySendError};
+use std::time::Duration;
+use std;
 
+use mio_more;
 use pool::{Pool, Checkout};
-use rtlsdr_mt::{Controller, Reader};
+use rtlsdr_mt::{Controller, Reader, TunerGains};
 
 use consts::{BUF_BYTES, BUF_COUNT};
+use hub::HubEvent;
+use iqfile::IqRecorder;
+use pipeline::{Pipeline, IQ_BACKLOG};
+use recv::RecvEvent;
+
+/// Delay before the first attempt to reopen a lost device.
+const REOPEN_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay between attempts to reopen a lost device.
+const REOPEN_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Produces chunks of interleaved 8-bit I/Q samples in the RTL-SDR format.
+pub trait SampleSource: Send {
+    /// Pass each chunk of samples to the given callback until the source is exhausted,
+    /// blocking the thread.
+    ///
+    /// Each chunk must be exactly `BUF_BYTES` long.
+    fn read(&mut self, cb: &mut dyn FnMut(&[u8])) -> std::io::Result<()>;
+
+    /// Whether samples are produced in real time.
+    ///
+    /// Samples from non-realtime sources are never dropped, so reading blocks until the
+    /// receiver catches up.
+    fn realtime(&self) -> bool { true }
+}
+
+/// Controls the tuning of a sample source.
+pub trait Tuner: Send {
+    /// Set the center frequency (Hz).
+    fn set_center_freq(&mut self, freq: u32) -> std::io::Result<()>;
+    /// Set the sample rate (samples/sec).
+    fn set_sample_rate(&mut self, rate: u32) -> std::io::Result<()>;
+    /// Set the frequency correction (PPM).
+    fn set_ppm(&mut self, ppm: i32) -> std::io::Result<()>;
+    /// Set a fixed tuner gain (tenths of a dB).
+    fn set_tuner_gain(&mut self, gain: i32) -> std::io::Result<()>;
+    /// Enable the tuner's automatic gain control.
+    fn enable_agc(&mut self) -> std::io::Result<()>;
+
+    /// Retrieve the supported fixed tuner gains (tenths of a dB), if they're known.
+    fn tuner_gains(&mut self) -> Vec<i32> { vec![] }
+}
+
+/// Gain and frequency correction applied to a tuner.
+#[derive(Copy, Clone, Default)]
+pub struct TunerSettings {
+    /// Fixed tuner gain (tenths of a dB), or `None` for automatic gain control.
+    pub gain: Option<i32>,
+    /// Frequency correction (PPM).
+    pub ppm: i32,
+}
+
+/// Sample source backed by a local RTL-SDR device.
+pub struct RtlSource(Reader);
+
+impl RtlSource {
+    /// Create a new `RtlSource` reading from the given device.
+    pub fn new(reader: Reader) -> Self {
+        RtlSource(reader)
+    }
+}
+
+impl SampleSource for RtlSource {
+    fn read(&mut self, cb: &mut dyn FnMut(&[u8])) -> std::io::Result<()> {
+        self.0.read_async(BUF_COUNT as u32, BUF_BYTES as u32, |bytes| cb(bytes))
+            .map_err(|_| sdr_error("error in async read"))
+    }
+}
+
+/// Tuner backed by a local RTL-SDR device.
+pub struct RtlTuner(Controller);
+
+impl RtlTuner {
+    /// Create a new `RtlTuner` controlling the given device.
+    pub fn new(control: Controller) -> Self {
+        RtlTuner(control)
+    }
+}
+
+impl Tuner for RtlTuner {
+    fn set_center_freq(&mut self, freq: u32) -> std::io::Result<()> {
+        self.0.set_center_freq(freq).map_err(|_| sdr_error("unable to set frequency"))
+    }
+
+    fn set_sample_rate(&mut self, rate: u32) -> std::io::Result<()> {
+        self.0.set_sample_rate(rate).map_err(|_| sdr_error("unable to set sample rate"))
+    }
+
+    fn set_ppm(&mut self, ppm: i32) -> std::io::Result<()> {
+        self.0.set_ppm(ppm).map_err(|_| sdr_error("unable to set ppm"))
+    }
+
+    fn set_tuner_gain(&mut self, gain: i32) -> std::io::Result<()> {
+        self.0.set_tuner_gain(gain).map_err(|_| sdr_error("unable to set gain"))
+    }
+
+    fn enable_agc(&mut self) -> std::io::Result<()> {
+        self.0.enable_agc().map_err(|_| sdr_error("unable to enable agc"))
+    }
+
+    fn tuner_gains(&mut self) -> Vec<i32> {
+        let mut gains = TunerGains::default();
+        self.0.tuner_gains(&mut gains).to_vec()
+    }
+}
+
+/// Stands in for the tuner of a lost device until the device is reopened.
+struct LostTuner;
+
+impl Tuner for LostTuner {
+    fn set_center_freq(&mut self, _: u32) -> std::io::Result<()> {
+        Err(sdr_error("device lost"))
+    }
+
+    fn set_sample_rate(&mut self, _: u32) -> std::io::Result<()> {
+        Err(sdr_error("device lost"))
+    }
+
+    fn set_ppm(&mut self, _: i32) -> std::io::Result<()> {
+        Err(sdr_error("device lost"))
+    }
+
+    fn set_tuner_gain(&mut self, _: i32) -> std::io::Result<()> {
+        Err(sdr_error("device lost"))
+    }
+
+    fn enable_agc(&mut self) -> std::io::Result<()> {
+        Err(sdr_error("device lost"))
+    }
+}
+
+/// Create an I/O error describing the given failed SDR operation.
+pub fn sdr_error(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, msg)
+}
+
+/// Opens a new connection to a device, returning its tuner and sample source.
+pub type Open = Box<dyn FnMut() -> std::io::Result<(Box<dyn Tuner>, Box<dyn SampleSource>)>
+                    + Send>;
+
+/// Reopens a lost device and hands its new tuner to the device's `ControlTask`.
+pub struct Reconnect {
+    /// Position of the device, with the control channel device at 0.
+    dev: usize,
+    /// Opens the device.
+    open: Open,
+    /// Channel for the device's `ControlTask`.
+    control: Sender<ControlTaskEvent>,
+    /// Channel for the hub.
+    hub: mio_more::channel::Sender<HubEvent>,
+}
+
+impl Reconnect {
+    /// Create a new `Reconnect` for the device at the given position, reopening it with
+    /// the given function and communicating on the given channels.
+    pub fn new(dev: usize,
+               open: Open,
+               control: Sender<ControlTaskEvent>,
+               hub: mio_more::channel::Sender<HubEvent>)
+        -> Self
+    {
+        Reconnect {
+            dev: dev,
+            open: open,
+            control: control,
+            hub: hub,
+        }
+    }
+
+    /// Report the device as lost due to the given error, close its given sample source
+    /// and its tuner, then reopen it, backing off between attempts, and return its new
+    /// sample source.
+    fn reopen(&mut self, err: std::io::Error, source: Box<dyn SampleSource>)
+        -> Box<dyn SampleSource>
+    {
+        warn!("lost device {}: {}", self.dev, err);
+
+        self.hub.send(HubEvent::UpdateDeviceStatus(self.dev, false))
+            .expect("unable to send device status");
+
+        // The old connection must be fully closed first, since an rtl_tcp server only
+        // serves one client and a local device can only be claimed once.
+        drop(source);
+
+        let (tx, rx) = channel();
+
+        self.control.send(ControlTaskEvent::Release(tx))
+            .expect("unable to release lost tuner");
+        rx.recv().expect("unable to release lost tuner");
+
+        let mut delay = REOPEN_DELAY;
+
+        loop {
+            std::thread::sleep(delay);
+
+            match (self.open)() {
+                Ok((tuner, source)) => {
+                    info!("reopened device {}", self.dev);
+
+                    // The controller restores the last settings onto the new tuner.
+                    self.control.send(ControlTaskEvent::Restore(tuner))
+                        .expect("unable to send restored tuner");
+                    self.hub.send(HubEvent::UpdateDeviceStatus(self.dev, true))
+                        .expect("unable to send device status");
+
+                    return source;
+                },
+                Err(e) => {
+                    debug!("unable to reopen device {}: {}", self.dev, e);
+                    delay = std::cmp::min(delay * 2, REOPEN_MAX_DELAY);
+                },
+            }
+        }
+    }
+}
+
+/// Chunk of interleaved 8-bit I/Q samples read from the SDR.
+pub struct Chunk {
+    /// Generation of the tuning the samples were captured under.
+    pub gen: u32,
+    /// Center frequency (Hz) the samples were captured at, or zero before the first
+    /// tuning.
+    pub freq: u32,
+    /// I/Q sample bytes.
+    pub bytes: Checkout<Vec<u8>>,
+}
+
+/// Messages for `ReadTask`.
+pub enum ReadTaskEvent {
+    /// The center frequency was changed to the contained value (Hz) under the contained
+    /// tuning generation.
+    Retune(u32, u32),
+}
 
 /// Reads chunks of samples from the SDR and sends them over a channel.
 pub struct ReadTask {
-    /// Channel to send chunks over.
-    chan: Sender<Checkout<Vec<u8>>>,
+    /// Channel to send chunks over, holding at most `IQ_BACKLOG` of them.
+    chan: SyncSender<Chunk>,
+    /// Current tuning generation.
+    gen: u32,
+    /// Current center frequency (Hz).
+    freq: u32,
+    /// Channel for messages.
+    events: Receiver<ReadTaskEvent>,
+    /// Optional recorder for raw samples.
+    rec: Option<IqRecorder<BufWriter<File>>>,
+    /// Reopens the device if it's lost, if supported.
+    reconnect: Option<Reconnect>,
+    /// Pipeline health counters.
+    pipeline: Arc<Pipeline>,
 }
 
 impl ReadTask {
-    /// Create a new `ReadTask` communicating over the given channel.
-    pub fn new(chan: Sender<Checkout<Vec<u8>>>) -> Self {
+    /// Create a new `ReadTask` communicating over the given channels, optionally
+    /// recording all samples into the given recorder, reopening a lost device with the
+    /// given `Reconnect`, and counting the chunks a live device had to drop in the given
+    /// pipeline counters.
+    pub fn new(chan: SyncSender<Chunk>,
+               events: Receiver<ReadTaskEvent>,
+               rec: Option<IqRecorder<BufWriter<File>>>,
+               reconnect: Option<Reconnect>,
+               pipeline: Arc<Pipeline>)
+        -> Self
+    {
         ReadTask {
             chan: chan,
+            gen: 0,
+            freq: 0,
+            events: events,
+            rec: rec,
+            reconnect: reconnect,
+            pipeline: pipeline,
+        }
+    }
+
+    /// Start reading samples from the given source, blocking the thread.
+    pub fn run(&mut self, mut source: Box<dyn SampleSource>) {
+        // Besides the queued chunks, one is being demodulated and one is being filled,
+        // so a buffer is always free.
+        let mut pool = Pool::with_capacity(IQ_BACKLOG + 2, || vec![0; BUF_BYTES]);
+
+        // Recorded samples can wait for the initial tuning, so none are dropped as stale.
+        if !source.realtime() {
+            if let Ok(e) = self.events.recv() {
+                self.handle_event(e);
+            }
+        }
+
+        loop {
+            let realtime = source.realtime();
+
+            let err = match source.read(&mut |bytes: &[u8]| {
+                // A live chunk was already being captured when any pending retune
+                // happened, so it belongs to the previous tuning, and the retune is
+                // recorded after it. This is only exact for a local device: a network
+                // source may still have older chunks in flight, which the receiver
+                // policy has to tolerate.
+                if !realtime {
+                    self.handle_events();
+                }
+
+                let (gen, freq) = (self.gen, self.freq);
+
+                if let Some(ref mut rec) = self.rec {
+                    rec.record(bytes);
+                }
+
+                if realtime {
+                    self.handle_events();
+                }
+
+                let mut samples = pool.checkout().expect("unable to allocate samples");
+                (&mut samples[..]).copy_from_slice(bytes);
+
+                let chunk = Chunk {
+                    gen: gen,
+                    freq: freq,
+                    bytes: samples,
+                };
+
+                self.pipeline.iq.push();
+
+                // Recorded samples wait for the demodulators when they fall behind.
+                if !realtime {
+                    self.chan.send(chunk).expect("unable to send sdr samples");
+                    return;
+                }
+
+                match self.chan.try_send(chunk) {
+                    Ok(()) => {},
+                    Err(TrySendError::Full(_)) => {
+                        // The device can't wait for the demodulators.
+                        self.pipeline.iq.pop();
+                        self.pipeline.record_sdr_drop();
+                    },
+                    Err(TrySendError::Disconnected(_)) =>
+                        panic!("unable to send sdr samples"),
+                }
+            }) {
+                Ok(()) if !realtime => break,
+                // A live device never runs out of samples, so it stopped because it was
+                // lost, even if no error was reported.
+                Ok(()) => sdr_error("device stopped streaming"),
+                Err(e) => e,
+            };
+
+            source = match self.reconnect {
+                Some(ref mut r) => r.reopen(err, source),
+                None => panic!("error in async read: {}", err),
+            };
         }
+
+        info!("sample source finished");
     }
 
-    /// Start reading samples, blocking the thread.
-    pub fn run(&mut self, mut reader: Reader) {
-        let mut pool = Pool::with_capacity(16, || vec![0; BUF_BYTES]);
+    /// Handle any pending messages.
+    fn handle_events(&mut self) {
+        while let Ok(e) = self.events.try_recv() {
+            self.handle_event(e);
+        }
+    }
+
+    /// Handle the given message.
+    fn handle_event(&mut self, e: ReadTaskEvent) {
+        match e {
+            ReadTaskEvent::Retune(freq, gen) => {
+                self.gen = gen;
+                self.freq = freq;
 
-        reader.read_async(BUF_COUNT as u32, BUF_BYTES as u32, |bytes| {
-            let mut samples = pool.checkout().expect("unable to allocate samples");
-            (&mut samples[..]).copy_from_slice(bytes);
-            self.chan.send(samples).expect("unable to send sdr samples");
-        }).expect("error in async read");
+                if let Some(ref mut rec) = self.rec {
+                    rec.retune(freq);
+                }
+            },
+        }
     }
 }
 
 /// Messages for `ControlTask`.
 pub enum ControlTaskEvent {
-    /// Set the center frequency to the contained value (Hz).
-    SetFreq(u32),
+    /// Tune to the channel at the contained frequency (Hz), tagging the samples that
+    /// follow with the contained tuning generation.
+    SetFreq(u32, u32),
+    /// Set a fixed tuner gain to the contained value (tenths of a dB).
+    SetGain(i32),
+    /// Enable automatic gain control.
+    EnableAgc,
+    /// Set the frequency correction to the contained value (PPM).
+    SetPpm(i32),
+    /// Close the SDR, which was lost, so it can be reopened, then acknowledge over the
+    /// contained channel.
+    Release(Sender<()>),
+    /// Replace the SDR with the contained reopened one and restore the last settings.
+    Restore(Box<dyn Tuner>),
 }
 
 /// Controls SDR parameters.
 pub struct ControlTask {
     /// SDR interface.
-    sdr: Controller,
+    sdr: Box<dyn Tuner>,
+    /// Sample rate (samples/sec).
+    rate: u32,
+    /// Last requested gain and frequency correction.
+    settings: TunerSettings,
+    /// Offset (Hz) of the center frequency from the channel, keeping the channel away
+    /// from the DC spike.
+    offset: i32,
+    /// Last requested channel frequency (Hz).
+    freq: Option<u32>,
+    /// Generation of the last requested tuning.
+    gen: u32,
     /// Channel for messages.
     events: Receiver<ControlTaskEvent>,
+    /// Channel for notifying the sample reader of changes.
+    read: Sender<ReadTaskEvent>,
+    /// Receiver chain the SDR feeds.
+    chain: usize,
+    /// Channel for notifying the receiver of failed retunes.
+    recv: Sender<RecvEvent>,
 }
 
 impl ControlTask {
-    /// Create a new `ControlTask` over the given SDR, receiving messages from the given
-    /// channel.
-    pub fn new(sdr: Controller, events: Receiver<ControlTaskEvent>) -> Self {
+    /// Create a new `ControlTask` over the given SDR, which has been configured with the
+    /// given sample rate and settings, tuning the given offset (Hz) away from each
+    /// channel, receiving messages from the given channel and notifying the given reader
+    /// of changes. Retunes that fail are reported to the given receiver as belonging to
+    /// the given chain.
+    pub fn new(sdr: Box<dyn Tuner>,
+               rate: u32,
+               settings: TunerSettings,
+               offset: i32,
+               events: Receiver<ControlTaskEvent>,
+               read: Sender<ReadTaskEvent>,
+               chain: usize,
+               recv: Sender<RecvEvent>)
+        -> Self
+    {
         ControlTask {
             sdr: sdr,
+            rate: rate,
+            settings: settings,
+            offset: offset,
+            freq: None,
+            gen: 0,
             events: events,
+            read: read,
+            chain: chain,
+            recv: recv,
         }
     }
 
@@ -61,9 +466,83 @@ impl ControlTask {
     pub fn run(&mut self) {
         loop {
             match self.events.recv().expect("unable to receive controller event") {
-                ControlTaskEvent::SetFreq(freq) =>
-                    self.sdr.set_center_freq(freq).expect("unable to set frequency"),
+                // Failures are only logged, since the device may have been lost and will
+                // have the requested settings restored once it's reopened.
+                ControlTaskEvent::SetFreq(freq, gen) => {
+                    self.freq = Some(freq);
+                    self.gen = gen;
+
+                    if let Err(e) = self.tune(freq) {
+                        warn!("unable to set frequency to {}: {}", freq, e);
+
+                        // Samples keep coming from the previous frequency, so the
+                        // receiver must stop waiting on this one.
+                        self.recv.send(RecvEvent::TuneFailed(self.chain, gen))
+                            .expect("unable to send tune failure");
+                    }
+                },
+                ControlTaskEvent::SetGain(gain) => {
+                    self.settings.gain = Some(gain);
+
+                    if let Err(e) = self.sdr.set_tuner_gain(gain) {
+                        warn!("unable to set gain to {}: {}", gain, e);
+                    }
+                },
+                ControlTaskEvent::EnableAgc => {
+                    self.settings.gain = None;
+
+                    if let Err(e) = self.sdr.enable_agc() {
+                        warn!("unable to enable agc: {}", e);
+                    }
+                },
+                ControlTaskEvent::SetPpm(ppm) => {
+                    self.settings.ppm = ppm;
+
+                    if let Err(e) = self.sdr.set_ppm(ppm) {
+                        warn!("unable to set ppm to {}: {}", ppm, e);
+                    }
+                },
+                ControlTaskEvent::Release(ack) => {
+                    self.sdr = Box::new(LostTuner);
+                    ack.send(()).expect("unable to acknowledge released tuner");
+                },
+                ControlTaskEvent::Restore(sdr) => {
+                    self.sdr = sdr;
+
+                    if let Err(e) = self.restore() {
+                        warn!("unable to restore settings: {}", e);
+                    }
+                },
             }
         }
     }
+
+    /// Apply the last requested settings to the SDR.
+    fn restore(&mut self) -> std::io::Result<()> {
+        self.sdr.set_sample_rate(self.rate)?;
+        self.sdr.set_ppm(self.settings.ppm)?;
+
+        match self.settings.gain {
+            Some(gain) => self.sdr.set_tuner_gain(gain)?,
+            None => self.sdr.enable_agc()?,
+        }
+
+        if let Some(freq) = self.freq {
+            self.tune(freq)?;
+        }
+
+        Ok(())
+    }
+
+    /// Tune the SDR to receive the channel at the given frequency (Hz).
+    fn tune(&mut self, freq: u32) -> std::io::Result<()> {
+        let center = (freq as i64 + self.offset as i64) as u32;
+
+        self.sdr.set_center_freq(center)?;
+
+        // The reader may have exited if the source was exhausted.
+        self.read.send(ReadTaskEvent::Retune(center, self.gen)).ok();
+
+        Ok(())
+    }
 }
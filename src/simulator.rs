@@ -0,0 +1,353 @@
+//! Simulated P25 trunking site for exercising the receiver in tests.
+//!
+//! The site's control channel continuously broadcasts trunking signaling packets
+//! advertising its channel plan and each call in progress, and each call's traffic
+//! channel carries voice frame groups followed by a terminator. Voice frame groups carry
+//! link control identifying the call or advertising other calls in progress, along with
+//! the call's encryption sync. Baseband is generated
+//! as a demodulator would produce it, so it can be fed straight to `RecvTask` for
+//! whatever frequency each chain is tuned to.
+
+use std::collections::HashMap;
+
+use consts::BASEBAND_SAMPLE_RATE;
+use modulator;
+
+/// NAC of the simulated site.
+pub const NAC: u16 = 0x293;
+
+/// Channel identifier of the site's channel plan.
+const CHANNEL_ID: u8 = 1;
+
+/// Base frequency (Hz) of the channel plan.
+const BASE_FREQ: u32 = 851_006_250;
+
+/// Spacing (Hz) between channels in the channel plan.
+const SPACING: u32 = 12_500;
+
+/// Bandwidth (Hz) of each channel in the channel plan.
+const BANDWIDTH: u32 = 12_500;
+
+/// Transmit offset (Hz) of subscribers below each channel in the channel plan.
+const TX_OFFSET: u32 = 45_000_000;
+
+/// Unit ID of the radio placing every call.
+const SOURCE: u32 = 0x00ABCD;
+
+/// Number of baseband samples per symbol.
+const SPS: usize = BASEBAND_SAMPLE_RATE as usize / 4800;
+
+/// Algorithm ID of unencrypted calls.
+const ALG_UNENCRYPTED: u8 = 0x80;
+
+/// Algorithm ID of AES-256 encrypted calls.
+const ALG_AES: u8 = 0x84;
+
+/// Group voice call on the site.
+#[derive(Copy, Clone, Debug)]
+pub struct Call {
+    /// Talkgroup ID.
+    pub talkgroup: u16,
+    /// Traffic channel number in the site's channel plan.
+    pub channel: u16,
+    /// Baseband sample when the call begins.
+    pub start: usize,
+    /// Number of LDU1/LDU2 pairs transmitted before the terminator.
+    pub ldus: usize,
+    /// Whether voice is transmitted on the traffic channel, or the call is only
+    /// advertised on the control channel.
+    pub transmit: bool,
+    /// Whether the call is encrypted.
+    pub encrypted: bool,
+}
+
+impl Call {
+    /// Build the dibits transmitted on the traffic channel over the course of the call,
+    /// with every other LDU1 advertising another of the given calls if one is in
+    /// progress.
+    fn traffic(&self, calls: &[Call]) -> Vec<u8> {
+        let mut dibits = vec![];
+
+        for n in 0..self.ldus {
+            let time = self.start + dibits.len() * SPS;
+
+            let other = calls.iter().find(|c| {
+                c.talkgroup != self.talkgroup && c.active(time)
+            });
+
+            let lc = match other {
+                Some(o) if n % 2 == 1 => lc_group_voice_update(o, self),
+                _ => lc_group_voice_traffic(self),
+            };
+
+            let ldu1 = modulator::ldu1_body(lc);
+            let ldu2 = modulator::ldu2_body(self.encryption_sync());
+
+            dibits.extend(modulator::ldu1_unit(NAC, &ldu1[..]));
+            dibits.extend(modulator::ldu2_unit(NAC, &ldu2[..]));
+        }
+
+        dibits.extend(modulator::tdu_unit(NAC));
+        dibits
+    }
+
+    /// Build the encryption sync word carried by the call's LDU2s.
+    fn encryption_sync(&self) -> [u8; 12] {
+        let mut es = [0; 12];
+
+        // Message indicator is left null, followed by the algorithm and key IDs.
+        if self.encrypted {
+            es[9] = ALG_AES;
+            es[11] = 0x01;
+        } else {
+            es[9] = ALG_UNENCRYPTED;
+        }
+
+        es
+    }
+
+    /// Baseband sample when the call's terminator ends.
+    pub fn end(&self) -> usize {
+        self.start + self.traffic(&[]).len() * SPS
+    }
+
+    /// Whether the call is in progress at the given baseband sample.
+    fn active(&self, time: usize) -> bool {
+        time >= self.start && time < self.end()
+    }
+}
+
+/// Trunking site carrying a fixed schedule of calls.
+pub struct Site {
+    /// Baseband on air at each frequency (Hz), starting from the first sample.
+    air: HashMap<u32, Vec<f32>>,
+}
+
+impl Site {
+    /// Create a new `Site` with its control channel at the given frequency (Hz),
+    /// carrying the given calls over the given amount of baseband samples.
+    pub fn new(ctlfreq: u32, calls: &[Call], duration: usize) -> Self {
+        let mut air = HashMap::new();
+
+        air.insert(ctlfreq, control(calls, duration));
+
+        for call in calls.iter() {
+            let buf = air.entry(Site::traffic_freq(call.channel))
+                .or_insert_with(|| vec![0.0; duration]);
+
+            if call.transmit {
+                let start = call.start.min(duration);
+                let bb = modulator::baseband(BASEBAND_SAMPLE_RATE,
+                                             &call.traffic(calls)[..]);
+                let len = bb.len().min(duration - start);
+
+                buf[start..start + len].copy_from_slice(&bb[..len]);
+            }
+        }
+
+        Site {
+            air: air,
+        }
+    }
+
+    /// Calculate the frequency (Hz) of the given traffic channel number.
+    pub fn traffic_freq(channel: u16) -> u32 {
+        BASE_FREQ + channel as u32 * SPACING
+    }
+
+    /// Get the given amount of baseband samples on air at the given frequency (Hz),
+    /// starting from the given sample.
+    ///
+    /// Frequencies without a transmitter are silent.
+    pub fn baseband(&self, freq: u32, start: usize, len: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; len];
+
+        if let Some(buf) = self.air.get(&freq) {
+            for (s, &b) in samples.iter_mut().zip(buf.iter().skip(start)) {
+                *s = b;
+            }
+        }
+
+        samples
+    }
+}
+
+/// Build the given amount of control channel baseband samples advertising the given
+/// calls.
+///
+/// Every other packet updates the channel plan, and the rest alternate between grants
+/// for each call in progress and traffic updates covering the first two.
+fn control(calls: &[Call], duration: usize) -> Vec<f32> {
+    let mut buf = Vec::with_capacity(duration);
+    let mut slot = 0;
+
+    while buf.len() < duration {
+        let active: Vec<&Call> = calls.iter().filter(|c| c.active(buf.len())).collect();
+        let idx = slot / 2;
+
+        let tsbk = if slot % 2 == 0 || active.is_empty() {
+            channel_params()
+        } else if active.len() >= 2 && idx % 2 == 1 {
+            group_voice_update(active[0], active[1])
+        } else {
+            group_voice_grant(active[idx / 2 % active.len()])
+        };
+
+        buf.extend(modulator::baseband(BASEBAND_SAMPLE_RATE,
+                                       &modulator::tsbk_unit(NAC, tsbk)[..]));
+        slot += 1;
+    }
+
+    buf.truncate(duration);
+    buf
+}
+
+/// Build an identifier update packet describing the site's channel plan.
+fn channel_params() -> [u8; 12] {
+    // Offset sign bit is clear for subscribers transmitting below the channel.
+    let word = (CHANNEL_ID as u64) << 60 |
+        (BANDWIDTH as u64 / 125) << 51 |
+        (TX_OFFSET as u64 / 250_000) << 42 |
+        (SPACING as u64 / 125) << 32 |
+        BASE_FREQ as u64 / 5;
+
+    modulator::tsbk(0x3D, 0x00, bytes(word))
+}
+
+/// Build a group voice grant packet for the given call.
+fn group_voice_grant(call: &Call) -> [u8; 12] {
+    let word = (channel(call) as u64) << 40 |
+        (call.talkgroup as u64) << 24 |
+        SOURCE as u64;
+
+    modulator::tsbk(0x00, 0x00, bytes(word))
+}
+
+/// Build a group voice channel update packet for the given pair of calls.
+fn group_voice_update(a: &Call, b: &Call) -> [u8; 12] {
+    let word = (channel(a) as u64) << 48 |
+        (a.talkgroup as u64) << 32 |
+        (channel(b) as u64) << 16 |
+        b.talkgroup as u64;
+
+    modulator::tsbk(0x02, 0x00, bytes(word))
+}
+
+/// Build a group voice link control word identifying the given call.
+fn lc_group_voice_traffic(call: &Call) -> [u8; 9] {
+    // Service options flag encryption.
+    let opts = if call.encrypted { 0x40 } else { 0x00 };
+
+    [0x00, 0x00, opts, 0x00, (call.talkgroup >> 8) as u8, call.talkgroup as u8,
+     (SOURCE >> 16) as u8, (SOURCE >> 8) as u8, SOURCE as u8]
+}
+
+/// Build a group voice channel update link control word for the given pair of calls.
+fn lc_group_voice_update(a: &Call, b: &Call) -> [u8; 9] {
+    let word = (channel(a) as u64) << 48 |
+        (a.talkgroup as u64) << 32 |
+        (channel(b) as u64) << 16 |
+        b.talkgroup as u64;
+
+    let mut lc = [0; 9];
+
+    lc[0] = 0x02;
+    lc[1..].copy_from_slice(&bytes(word)[..]);
+
+    lc
+}
+
+/// Encode the traffic channel of the given call as a channel identifier and number.
+fn channel(call: &Call) -> u16 {
+    (CHANNEL_ID as u16) << 12 | call.channel & 0xFFF
+}
+
+/// Split the given word into big-endian bytes.
+fn bytes(word: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+
+    for (n, b) in bytes.iter_mut().enumerate() {
+        *b = (word >> (56 - n * 8)) as u8;
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(bytes(0x0123456789ABCDEF),
+                   [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+    }
+
+    #[test]
+    fn test_packets() {
+        let call = Call {
+            talkgroup: 0x1234,
+            channel: 0x123,
+            start: 0,
+            ldus: 1,
+            transmit: true,
+            encrypted: true,
+        };
+
+        // Same layout as the grant used by the demodulator tests.
+        assert_eq!(group_voice_grant(&call)[2..10],
+                   [0x00, 0x11, 0x23, 0x12, 0x34, 0x00, 0xAB, 0xCD]);
+
+        assert_eq!(group_voice_update(&call, &call)[2..10],
+                   [0x11, 0x23, 0x12, 0x34, 0x11, 0x23, 0x12, 0x34]);
+
+        // Link control carries the same update after its opcode.
+        assert_eq!(lc_group_voice_update(&call, &call)[..],
+                   [0x02, 0x11, 0x23, 0x12, 0x34, 0x11, 0x23, 0x12, 0x34]);
+        assert_eq!(lc_group_voice_traffic(&call)[..],
+                   [0x00, 0x00, 0x40, 0x00, 0x12, 0x34, 0x00, 0xAB, 0xCD]);
+        assert_eq!(call.encryption_sync()[9..], [ALG_AES, 0x00, 0x01]);
+
+        let params = channel_params();
+
+        assert_eq!(params[0], 0x80 | 0x3D);
+        assert_eq!(params[2] >> 4, CHANNEL_ID);
+        assert_eq!(((params[4] as u32 & 0x3) << 8 | params[5] as u32) * 125, SPACING);
+        assert_eq!(((params[6] as u32) << 24 | (params[7] as u32) << 16 |
+                    (params[8] as u32) << 8 | params[9] as u32) * 5, BASE_FREQ);
+    }
+
+    #[test]
+    fn test_site() {
+        let call = Call {
+            talkgroup: 0x101,
+            channel: 10,
+            start: 4800,
+            ldus: 1,
+            transmit: true,
+            encrypted: false,
+        };
+
+        let site = Site::new(851_012_500, &[call], 48000);
+        let traffic = Site::traffic_freq(10);
+
+        assert_eq!(traffic, 851_131_250);
+
+        // Control channel is always on air.
+        let bb = site.baseband(851_012_500, 0, 48000);
+        assert_eq!(bb.len(), 48000);
+        assert!(bb.iter().all(|&s| s != 0.0));
+
+        // Traffic channel is silent until the call starts and after it ends.
+        let bb = site.baseband(traffic, 0, 48000);
+        assert!(bb[..4800].iter().all(|&s| s == 0.0));
+        assert!(bb[4800..call.end()].iter().all(|&s| s != 0.0));
+        assert!(bb[call.end()..].iter().all(|&s| s == 0.0));
+
+        // Reading past the end is silent.
+        assert_eq!(site.baseband(traffic, 47990, 20)[10..], [0.0; 10]);
+
+        // Unused frequencies are silent.
+        assert_eq!(site.baseband(860_000_000, 0, 10), vec![0.0; 10]);
+    }
+}
@@ -0,0 +1,68 @@
+//! Sample sources that don't require SDR hardware.
+
+use std::time::{Duration, Instant};
+use std;
+
+use rand::{self, Rng};
+
+use consts::{BUF_BYTES, BUF_SAMPLES};
+use sdr::{SampleSource, Tuner};
+
+/// Generates low-level random noise in real time.
+///
+/// This allows the full receiver pipeline to run on machines without an SDR.
+pub struct NoiseSource {
+    /// Sample rate to generate samples at.
+    rate: u32,
+}
+
+impl NoiseSource {
+    /// Create a new `NoiseSource` generating samples at the given rate (samples/sec).
+    pub fn new(rate: u32) -> Self {
+        NoiseSource {
+            rate: rate,
+        }
+    }
+}
+
+impl SampleSource for NoiseSource {
+    fn read(&mut self, cb: &mut dyn FnMut(&[u8])) -> std::io::Result<()> {
+        let mut rng = rand::thread_rng();
+        let mut buf = vec![0; BUF_BYTES];
+
+        // Time spanned by each chunk of samples.
+        let period = Duration::from_secs(BUF_SAMPLES as u64) / self.rate;
+        let mut next = Instant::now();
+
+        loop {
+            for b in buf.iter_mut() {
+                // Stay within a few steps of the midpoint 127.5, which maps to zero.
+                *b = rng.gen_range(124..132);
+            }
+
+            cb(&buf[..]);
+
+            // Pace chunks to the sample rate rather than sending as fast as possible.
+            next += period;
+
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// Tuner that accepts and ignores all tuning requests.
+pub struct NullTuner;
+
+impl Tuner for NullTuner {
+    fn set_center_freq(&mut self, freq: u32) -> std::io::Result<()> {
+        debug!("ignoring tune to {} Hz", freq);
+        Ok(())
+    }
+
+    fn set_sample_rate(&mut self, _: u32) -> std::io::Result<()> { Ok(()) }
+    fn set_ppm(&mut self, _: i32) -> std::io::Result<()> { Ok(()) }
+    fn set_tuner_gain(&mut self, _: i32) -> std::io::Result<()> { Ok(()) }
+    fn enable_agc(&mut self) -> std::io::Result<()> { Ok(()) }
+}
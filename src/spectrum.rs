@@ -0,0 +1,170 @@
+//! Power spectrum estimation for spectrum and waterfall displays.
+
+use std::f32::consts::PI;
+
+use num::complex::Complex32;
+use num::traits::Zero;
+
+/// Number of frequency bins in each spectrum frame.
+pub const SPECTRUM_BINS: usize = 1024;
+
+/// Estimates the power spectrum of I/Q signal by averaging windowed FFTs.
+pub struct Spectrum {
+    /// Window applied to each FFT input.
+    window: Vec<f32>,
+    /// FFT working buffer.
+    buf: Vec<Complex32>,
+}
+
+impl Spectrum {
+    /// Create a new `Spectrum` with the given number of bins, which must be a power of
+    /// two.
+    pub fn new(bins: usize) -> Self {
+        assert!(bins.is_power_of_two());
+
+        Spectrum {
+            // Hann window.
+            window: (0..bins).map(|n| {
+                0.5 - 0.5 * (2.0 * PI * n as f32 / bins as f32).cos()
+            }).collect(),
+            buf: vec![Complex32::zero(); bins],
+        }
+    }
+
+    /// Calculate the power, relative to a full scale tone, in each bin of the given
+    /// samples, ordered from the lowest frequency to the highest, with the center
+    /// frequency in the middle.
+    ///
+    /// Every full window of samples is averaged, and the rest are ignored.
+    pub fn power(&mut self, samples: &[Complex32]) -> Vec<f32> {
+        let bins = self.window.len();
+        let mut power = vec![0.0; bins];
+        let mut count = 0;
+
+        for chunk in samples.chunks(bins).filter(|c| c.len() == bins) {
+            for ((b, &s), &w) in self.buf.iter_mut().zip(chunk).zip(&self.window) {
+                *b = s * w;
+            }
+
+            fft(&mut self.buf[..]);
+
+            for (p, b) in power.iter_mut().zip(&self.buf) {
+                *p += b.norm_sqr();
+            }
+
+            count += 1;
+        }
+
+        // Full scale sinusoid through the window sums to half the bins.
+        let scale = (bins * bins) as f32 / 4.0 * count.max(1) as f32;
+
+        // Swap halves so negative frequencies come first.
+        let (hi, lo) = power.split_at(bins / 2);
+
+        lo.iter().chain(hi).map(|&p| p / scale).collect()
+    }
+}
+
+/// Convert the given power ratio to decibels.
+pub fn decibels(power: f32) -> f32 {
+    10.0 * power.max(1e-20).log10()
+}
+
+/// Transform the given samples, whose length must be a power of two, into the frequency
+/// domain in place.
+fn fft(buf: &mut [Complex32]) {
+    let len = buf.len();
+    assert!(len.is_power_of_two());
+
+    if len < 2 {
+        return;
+    }
+
+    let bits = len.trailing_zeros();
+
+    // Reorder into bit-reversed index order.
+    for i in 0..len {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+
+        if i < j {
+            buf.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+
+    while size <= len {
+        let step = Complex32::from_polar(1.0, -2.0 * PI / size as f32);
+
+        for start in (0..len).step_by(size) {
+            let mut w = Complex32::new(1.0, 0.0);
+
+            for k in 0..size / 2 {
+                let a = buf[start + k];
+                let b = buf[start + k + size / 2] * w;
+
+                buf[start + k] = a + b;
+                buf[start + k + size / 2] = a - b;
+
+                w = w * step;
+            }
+        }
+
+        size *= 2;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fft() {
+        let mut buf = [Complex32::zero(); 8];
+        buf[0] = Complex32::new(1.0, 0.0);
+        fft(&mut buf[..]);
+
+        for b in buf.iter() {
+            assert!((b - Complex32::new(1.0, 0.0)).norm() < 1e-6);
+        }
+
+        // Tone at the second bin.
+        let mut buf: Vec<Complex32> = (0..8).map(|n| {
+            Complex32::from_polar(1.0, 2.0 * PI * 2.0 * n as f32 / 8.0)
+        }).collect();
+
+        fft(&mut buf[..]);
+
+        for (i, b) in buf.iter().enumerate() {
+            if i == 2 {
+                assert!((b.norm() - 8.0).abs() < 1e-4);
+            } else {
+                assert!(b.norm() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decibels() {
+        assert_eq!(decibels(1.0), 0.0);
+        assert!((decibels(0.5) + 3.0103).abs() < 1e-4);
+        assert_eq!(decibels(0.0), -200.0);
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let mut s = Spectrum::new(64);
+
+        // Full scale tone a quarter of the rate above the center.
+        let samples: Vec<Complex32> = (0..64 * 4 + 10).map(|n| {
+            Complex32::from_polar(1.0, 2.0 * PI * 16.0 * n as f32 / 64.0)
+        }).collect();
+
+        let power = s.power(&samples[..]);
+
+        assert_eq!(power.len(), 64);
+        assert!((power[48] - 1.0).abs() < 1e-3);
+        assert!(decibels(power[32]) < -100.0);
+        assert!(decibels(power[16]) < -100.0);
+    }
+}
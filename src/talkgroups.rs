@@ -1,6 +1,7 @@
 //! Talkgroup collection and selection.
 
 use std::collections::hash_map::HashMap;
+use std;
 use std::collections::HashSet;
 
 use fnv::FnvBuildHasher;
@@ -9,6 +10,9 @@ use p25::voice::crypto::CryptoAlgorithm;
 /// Maps talkgroups to associated encryption algorithm.
 pub type GroupCryptoMap = HashMap<u16, CryptoAlgorithm, FnvBuildHasher>;
 
+/// Maps supergroups to the talkgroups patched onto them.
+pub type PatchMap = HashMap<u16, Vec<u16>, FnvBuildHasher>;
+
 /// Collects, prioritizes, filters, and selects talkgroups.
 #[derive(Default)]
 pub struct TalkgroupSelection {
@@ -22,6 +26,8 @@ pub struct TalkgroupSelection {
     encrypted: GroupCryptoMap,
     /// Set of talkgroups that can preempt a conversation.
     preempt: HashSet<u16, FnvBuildHasher>,
+    /// Talkgroups currently patched onto each supergroup.
+    patches: PatchMap,
     /// User-set included/excluded talkgroups.
     filter: Filter,
     /// Talkgroup selection features.
@@ -35,8 +41,15 @@ impl TalkgroupSelection {
     }
 
     /// Consider the given talkgroup for the current set of candidate talkgroups.
+    ///
+    /// A supergroup carries the conversations of its patched talkgroups, so it's only
+    /// excluded if all of them are, and it can preempt if any of them can.
     pub fn add_talkgroup(&mut self, tg: u16, freq: u32) {
-        if self.encrypted.contains_key(&tg) || self.filter.excluded(tg) {
+        let members = self.members(tg);
+
+        if self.encrypted.contains_key(&tg) ||
+           members.iter().all(|&m| self.filter.excluded(m))
+        {
             return;
         }
 
@@ -50,7 +63,7 @@ impl TalkgroupSelection {
 
         self.cur.push(tg);
 
-        if self.preempt.contains(&tg) {
+        if members.iter().any(|m| self.preempt.contains(m)) {
             self.cur_preempt.push(tg);
         }
     }
@@ -62,7 +75,7 @@ impl TalkgroupSelection {
     /// return `None` if no talkgroups are available.
     pub fn select_idle(&mut self) -> Option<(u16, u32)> {
         debug!("selecting from {} talkgroups", self.cur.len());
-        self.feats.max_score(&self.cur).map(|tg| self.select_tg(tg))
+        self.feats.max_score(&self.cur, &self.patches).map(|tg| self.select_tg(tg))
     }
 
     /// Select a talkgroup from the set of candidate preempting talkgroups.
@@ -71,7 +84,8 @@ impl TalkgroupSelection {
     /// talkgroup ID and `freq` is the traffic channel center frequency (Hz). Otherwise,
     /// return `None` if no talkgroups are available.
     pub fn select_preempt(&mut self) -> Option<(u16, u32)> {
-        self.feats.max_score(&self.cur_preempt).map(|tg| self.select_tg(tg))
+        self.feats.max_score(&self.cur_preempt, &self.patches)
+            .map(|tg| self.select_tg(tg))
     }
 
     /// Record that the given talkgroup is encrypted.
@@ -80,6 +94,51 @@ impl TalkgroupSelection {
         self.encrypted.insert(tg, alg);
     }
 
+    /// Allow the given talkgroup to preempt a conversation.
+    #[cfg(test)]
+    pub fn add_preempt(&mut self, tg: u16) {
+        self.preempt.insert(tg);
+    }
+
+    /// Record that the given talkgroups were patched onto the given supergroup.
+    pub fn add_patch(&mut self, sg: u16, groups: &[u16]) {
+        let members = self.patches.entry(sg).or_insert_with(Vec::new);
+
+        for &tg in groups.iter() {
+            if tg != sg && !members.contains(&tg) {
+                debug!("patching talkgroup {} onto supergroup {}", tg, sg);
+                members.push(tg);
+            }
+        }
+    }
+
+    /// Record that the given talkgroups were removed from the given supergroup.
+    pub fn remove_patch(&mut self, sg: u16, groups: &[u16]) {
+        let empty = match self.patches.get_mut(&sg) {
+            Some(members) => {
+                members.retain(|tg| !groups.contains(tg));
+                members.is_empty()
+            },
+            None => return,
+        };
+
+        if empty {
+            debug!("removing supergroup {}", sg);
+            self.patches.remove(&sg);
+        }
+    }
+
+    /// Retrieve the given talkgroup along with any talkgroups patched onto it.
+    fn members(&self, tg: u16) -> Vec<u16> {
+        let mut members = vec![tg];
+
+        if let Some(patched) = self.patches.get(&tg) {
+            members.extend_from_slice(&patched[..]);
+        }
+
+        members
+    }
+
     /// Finalize selection of the given talkgroup.
     fn select_tg(&mut self, tg: u16) -> (u16, u32) {
         debug!("using talkgroup {}", tg);
@@ -103,6 +162,7 @@ impl TalkgroupSelection {
     pub fn clear_state(&mut self) {
         self.clear_candidates();
         self.encrypted.clear();
+        self.patches.clear();
         self.feats.reset();
     }
 }
@@ -154,8 +214,9 @@ impl TalkgroupFeatures {
 
     /// Find the talkgroup with the highest score in the given candidate talkgroups.
     ///
-    /// Each talkgroup must have been previously recorded with the `add` method.
-    pub fn max_score(&self, groups: &[u16]) -> Option<u16> {
+    /// Each talkgroup must have been previously recorded with the `add` method. A
+    /// supergroup in the given patches takes the highest priority of its members.
+    pub fn max_score(&self, groups: &[u16], patches: &PatchMap) -> Option<u16> {
         let oldest = self.oldest() as f32;
 
         // If the oldest talkgroup has no age, then none of the others will either, so
@@ -167,8 +228,13 @@ impl TalkgroupFeatures {
             let age = 1.0 - self.elapsed.wrapping_sub(self.age[&tg]) as f32 * mul;
             // Recent talkgroup gets a reward.
             let recent = if tg == self.recent { 1.0 } else { 0.0 };
+            // Supergroups are as important as their most important member.
+            let prio = patches.get(&tg).into_iter().flat_map(|p| p.iter())
+                .chain(Some(&tg))
+                .map(|m| *self.prios.get(m).unwrap_or(&1.0))
+                .fold(std::f32::MIN, f32::max);
 
-            self.prios.get(&tg).unwrap_or(&1.0) * self.weights.prio +
+            prio * self.weights.prio +
             age * self.weights.age +
             recent * self.weights.recent
         };
@@ -356,4 +422,51 @@ mod test {
         assert!(ts.encrypted.is_empty());
         assert_eq!(ts.feats.recent, 0);
     }
+
+    #[test]
+    fn test_patches() {
+        let mut ts = TalkgroupSelection::default();
+
+        ts.add_patch(1000, &[10, 20]);
+        ts.add_patch(1000, &[20, 30, 1000]);
+        assert_eq!(&ts.patches[&1000][..], &[10, 20, 30]);
+        assert_eq!(ts.members(1000), vec![1000, 10, 20, 30]);
+        assert_eq!(ts.members(10), vec![10]);
+
+        // Supergroup is followed while any member is included.
+        ts.filter.filt.insert(10);
+        ts.filter.filt.insert(1000);
+        ts.add_talkgroup(1000, 100);
+        assert_eq!(&ts.cur[..], &[1000]);
+        ts.filter.filt.insert(20);
+        ts.filter.filt.insert(30);
+        ts.add_talkgroup(1000, 100);
+        ts.add_talkgroup(2000, 200);
+        assert_eq!(&ts.cur[..], &[1000, 2000]);
+
+        ts.clear_candidates();
+        ts.filter.filt.clear();
+
+        // Supergroup inherits the priority and preemption of its members.
+        ts.feats.prios.insert(30, 100.0);
+        ts.preempt.insert(20);
+        ts.add_talkgroup(40, 400);
+        ts.add_talkgroup(1000, 100);
+        assert_eq!(&ts.cur_preempt[..], &[1000]);
+        assert_eq!(ts.select_idle(), Some((1000, 100)));
+
+        // Removing every member removes the patch.
+        ts.remove_patch(1000, &[10, 20]);
+        assert_eq!(&ts.patches[&1000][..], &[30]);
+        ts.remove_patch(1000, &[30]);
+        assert!(ts.patches.is_empty());
+        ts.remove_patch(1000, &[30]);
+
+        ts.add_talkgroup(1000, 100);
+        assert!(ts.cur_preempt.is_empty());
+
+        ts.add_patch(1000, &[10]);
+        ts.clear_state();
+        assert!(ts.patches.is_empty());
+    }
 }